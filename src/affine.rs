@@ -0,0 +1,103 @@
+use crate::matrix::{BitMatrix128, BitMatrix32, BitMatrix64, Error};
+use core::ops::{BitXor, Mul};
+
+/// Map `x * linear ^ constant`, which models XOR constants and forced bits
+/// that a plain matrix can not
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AffineMap<M, V> {
+    pub linear: M,
+    pub constant: V,
+}
+
+impl<M, V> AffineMap<M, V>
+where
+    M: Copy + Mul<Output = M>,
+    V: Copy + Mul<M, Output = V> + BitXor<Output = V>,
+{
+    pub fn new(linear: M, constant: V) -> Self {
+        Self { linear, constant }
+    }
+
+    pub fn apply(&self, vec: V) -> V {
+        (vec * self.linear) ^ self.constant
+    }
+
+    /// Apply `self`, then `next`
+    pub fn then(&self, next: &Self) -> Self {
+        Self {
+            linear: self.linear * next.linear,
+            constant: (self.constant * next.linear) ^ next.constant,
+        }
+    }
+}
+
+macro_rules! impl_affine {
+    ($alias:ident, $name:ident, $ty:ty) => {
+        pub type $alias = AffineMap<$name, $ty>;
+
+        impl AffineMap<$name, $ty> {
+            pub fn identity() -> Self {
+                Self::new($name::eye(), 0)
+            }
+
+            pub fn xor(constant: $ty) -> Self {
+                Self::new($name::eye(), constant)
+            }
+
+            /// `x | mask`, the bits of `mask` are forced to one
+            pub fn or(mask: $ty) -> Self {
+                Self::new($name::eye().and(!mask), mask)
+            }
+
+            /// `x & mask`, the bits outside of `mask` are forced to zero
+            pub fn and(mask: $ty) -> Self {
+                Self::new($name::eye().and(mask), 0)
+            }
+
+            /// Recover the map of an affine closure, see `check_linearity`
+            /// for making sure it is one
+            pub fn from_fn(f: impl Fn($ty) -> $ty) -> Self {
+                Self::new($name::from_fn(&f), f(0))
+            }
+
+            /// Output bits that do not depend on the input, they always
+            /// have the value from `constant`
+            pub fn fixed_bits(&self) -> $ty {
+                !self.linear.0.iter().fold(0, |acc, row| acc | row)
+            }
+
+            pub fn inv(&self) -> Result<Self, Error> {
+                let linear = self.linear.inv()?;
+                Ok(Self::new(linear, self.constant * linear))
+            }
+        }
+    };
+}
+
+impl_affine!(AffineMap32, BitMatrix32, u32);
+impl_affine!(AffineMap64, BitMatrix64, u64);
+impl_affine!(AffineMap128, BitMatrix128, u128);
+
+#[cfg(test)]
+mod tests {
+    use super::{AffineMap, AffineMap32};
+    use crate::matrix::BitMatrix32 as M32;
+
+    #[test]
+    fn test_affine() {
+        let step = AffineMap::new(M32::eye() ^ M32::shl(5), 0xdead);
+        let forced = AffineMap32::or(0x100010);
+        let both = step.then(&forced);
+        for value in [0, 0x1337, 0xcafebabe] {
+            let expected = (value ^ (value << 5) ^ 0xdead) | 0x100010;
+            assert_eq!(both.apply(value), expected);
+            assert_eq!(step.inv().unwrap().apply(step.apply(value)), value);
+        }
+        assert_eq!(both.fixed_bits(), 0x100010);
+        assert!(forced.inv().is_err());
+        assert_eq!(
+            AffineMap32::from_fn(|value| (value ^ 0x55) & 0xff00ff),
+            AffineMap32::xor(0x55).then(&AffineMap32::and(0xff00ff))
+        );
+    }
+}
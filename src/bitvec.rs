@@ -0,0 +1,261 @@
+use core::fmt;
+
+/// Fixed width vector over GF(2), the primitive ints and `[u64; N]`. Bit
+/// `ii` lives in word `ii / 64`, so the words of a `u128` are its low and
+/// high halves and converting between the two keeps every bit in place.
+pub trait BitVector: Copy + Eq + fmt::Debug {
+    const BITS: usize;
+    const WORDS: usize = Self::BITS.div_ceil(64);
+
+    fn zero() -> Self;
+
+    /// Word `ii`, the bits `64 * ii..64 * ii + 64`
+    fn word(&self, ii: usize) -> u64;
+
+    fn set_word(&mut self, ii: usize, word: u64);
+
+    fn xor(self, other: Self) -> Self;
+
+    fn and(self, other: Self) -> Self;
+
+    fn or(self, other: Self) -> Self;
+
+    fn not(self) -> Self;
+
+    /// Move every bit `n` places up, towards the higher indices
+    fn shl(self, n: usize) -> Self;
+
+    fn shr(self, n: usize) -> Self;
+
+    /// Vector with only `bit` set
+    fn unit(bit: usize) -> Self {
+        let mut vec = Self::zero();
+        vec.set(bit, true);
+        vec
+    }
+
+    fn get(&self, bit: usize) -> bool {
+        (self.word(bit / 64) >> (bit % 64)) & 1 != 0
+    }
+
+    fn set(&mut self, bit: usize, value: bool) {
+        let word = self.word(bit / 64) & !(1 << (bit % 64));
+        self.set_word(bit / 64, word | (value as u64) << (bit % 64));
+    }
+
+    fn flip(&mut self, bit: usize) {
+        self.set(bit, !self.get(bit));
+    }
+
+    fn is_zero(&self) -> bool {
+        (0..Self::WORDS).all(|ii| self.word(ii) == 0)
+    }
+
+    fn count_ones(&self) -> usize {
+        (0..Self::WORDS)
+            .map(|ii| self.word(ii).count_ones() as usize)
+            .sum()
+    }
+
+    /// Lowest set bit, `None` for the zero vector
+    fn first_one(&self) -> Option<usize> {
+        let ii = (0..Self::WORDS).find(|&ii| self.word(ii) != 0)?;
+        Some(ii * 64 + self.word(ii).trailing_zeros() as usize)
+    }
+
+    /// The `len <= 64` bits starting at `start`, bits past the end are zero
+    fn bits(&self, start: usize, len: u32) -> u64 {
+        let (ii, shift) = (start / 64, start % 64);
+        let mut bits = self.word(ii) >> shift;
+        if shift != 0 && ii + 1 < Self::WORDS {
+            bits |= self.word(ii + 1) << (64 - shift);
+        }
+        if len < 64 {
+            bits &= (1 << len) - 1;
+        }
+        bits
+    }
+
+    /// XOR `bits` into the vector starting at `start`, the bits that do
+    /// not fit are dropped
+    fn xor_bits(&mut self, start: usize, bits: u64) {
+        let (ii, shift) = (start / 64, start % 64);
+        self.set_word(ii, self.word(ii) ^ (bits << shift));
+        if shift != 0 && ii + 1 < Self::WORDS {
+            let next = self.word(ii + 1) ^ (bits >> (64 - shift));
+            self.set_word(ii + 1, next);
+        }
+    }
+}
+
+macro_rules! impl_bitvector {
+    ($ty:ty) => {
+        impl BitVector for $ty {
+            const BITS: usize = <$ty>::BITS as usize;
+
+            fn zero() -> Self {
+                0
+            }
+
+            fn word(&self, ii: usize) -> u64 {
+                assert!(ii < Self::WORDS, "word index out of range");
+                (*self as u128 >> (ii * 64)) as u64
+            }
+
+            fn set_word(&mut self, ii: usize, word: u64) {
+                assert!(ii < Self::WORDS, "word index out of range");
+                let shift = ii * 64;
+                let value = (*self as u128 & !((u64::MAX as u128) << shift))
+                    | (word as u128) << shift;
+                *self = value as $ty;
+            }
+
+            fn xor(self, other: Self) -> Self {
+                self ^ other
+            }
+
+            fn and(self, other: Self) -> Self {
+                self & other
+            }
+
+            fn or(self, other: Self) -> Self {
+                self | other
+            }
+
+            fn not(self) -> Self {
+                !self
+            }
+
+            fn shl(self, n: usize) -> Self {
+                self.checked_shl(n as u32).unwrap_or(0)
+            }
+
+            fn shr(self, n: usize) -> Self {
+                self.checked_shr(n as u32).unwrap_or(0)
+            }
+
+            fn count_ones(&self) -> usize {
+                <$ty>::count_ones(*self) as usize
+            }
+
+            fn first_one(&self) -> Option<usize> {
+                (*self != 0).then(|| self.trailing_zeros() as usize)
+            }
+        }
+    };
+}
+
+impl_bitvector!(u32);
+impl_bitvector!(u64);
+impl_bitvector!(u128);
+
+impl<const N: usize> BitVector for [u64; N] {
+    const BITS: usize = 64 * N;
+
+    fn zero() -> Self {
+        [0; N]
+    }
+
+    fn word(&self, ii: usize) -> u64 {
+        self[ii]
+    }
+
+    fn set_word(&mut self, ii: usize, word: u64) {
+        self[ii] = word;
+    }
+
+    fn xor(self, other: Self) -> Self {
+        core::array::from_fn(|ii| self[ii] ^ other[ii])
+    }
+
+    fn and(self, other: Self) -> Self {
+        core::array::from_fn(|ii| self[ii] & other[ii])
+    }
+
+    fn or(self, other: Self) -> Self {
+        core::array::from_fn(|ii| self[ii] | other[ii])
+    }
+
+    fn not(self) -> Self {
+        self.map(|word| !word)
+    }
+
+    fn shl(self, n: usize) -> Self {
+        let (words, bits) = (n / 64, n % 64);
+        core::array::from_fn(|ii| {
+            let Some(src) = ii.checked_sub(words) else {
+                return 0;
+            };
+            let mut word = self[src] << bits;
+            if bits != 0 && src > 0 {
+                word |= self[src - 1] >> (64 - bits);
+            }
+            word
+        })
+    }
+
+    fn shr(self, n: usize) -> Self {
+        let (words, bits) = (n / 64, n % 64);
+        core::array::from_fn(|ii| {
+            let src = ii + words;
+            if src >= N {
+                return 0;
+            }
+            let mut word = self[src] >> bits;
+            if bits != 0 && src + 1 < N {
+                word |= self[src + 1] << (64 - bits);
+            }
+            word
+        })
+    }
+}
+
+/// Convert between vectors of any width, bits past the end of `to` are
+/// dropped and the missing ones are zero
+pub fn convert<T: BitVector, U: BitVector>(from: T) -> U {
+    let mut to = U::zero();
+    for ii in 0..T::WORDS.min(U::WORDS) {
+        to.set_word(ii, from.word(ii));
+    }
+    to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{convert, BitVector};
+
+    fn check_shifts<V: BitVector>(vec: V) {
+        for n in [0, 1, 31, 63, 64, 65, 100, V::BITS - 1, V::BITS] {
+            let shifted = vec.shl(n);
+            for bit in 0..V::BITS {
+                let expected = bit >= n && vec.get(bit - n);
+                assert_eq!(shifted.get(bit), expected, "{vec:?} << {n}");
+            }
+            assert_eq!(vec.shr(n).shl(n), vec.and(V::zero().not().shl(n)));
+        }
+    }
+
+    #[test]
+    fn test_bitvector() {
+        let value = 0x0123456789abcdef_fedcba9876543210_u128;
+        let words: [u64; 2] = convert(value);
+        assert_eq!(words, [0xfedcba9876543210, 0x0123456789abcdef]);
+        assert_eq!(convert::<_, u128>(words), value);
+        assert_eq!(convert::<_, u32>(words), 0x76543210);
+        assert_eq!(words.bits(60, 8), 0xff);
+        assert_eq!(value.bits(60, 8), 0xff);
+        assert_eq!(words.count_ones(), 64);
+        assert_eq!([0, 0, 0x10].first_one(), Some(132));
+
+        let mut vec = [0_u64; 3];
+        vec.xor_bits(120, 0xabcd);
+        assert_eq!(vec.bits(120, 16), 0xabcd);
+        vec.set(5, true);
+        vec.flip(120);
+        assert_eq!(vec, [0x20, 0xcc << 56, 0xab]);
+
+        check_shifts(value);
+        check_shifts(words);
+        check_shifts([0xdeadbeef_u64, 0x13371337, !0]);
+    }
+}
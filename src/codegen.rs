@@ -0,0 +1,243 @@
+use crate::matrix::{XorShiftOp, XorShiftSteps};
+use core::fmt::Write;
+
+/// Target language of the generated step functions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lang {
+    Rust,
+    /// Uses `stdint.h` types, 128-bit words need `unsigned __int128`
+    C,
+    /// 128-bit words need `UInt128` from .NET 7
+    CSharp,
+    Python,
+}
+
+impl Lang {
+    fn word_type(self, bits: u32) -> &'static str {
+        match (self, bits) {
+            (Lang::Rust, 32) => "u32",
+            (Lang::Rust, 64) => "u64",
+            (Lang::Rust, 128) => "u128",
+            (Lang::C, 32) => "uint32_t",
+            (Lang::C, 64) => "uint64_t",
+            (Lang::C, 128) => "unsigned __int128",
+            (Lang::CSharp, 32) => "uint",
+            (Lang::CSharp, 64) => "ulong",
+            (Lang::CSharp, 128) => "UInt128",
+            (Lang::Python, _) => "int",
+            _ => panic!("unsupported word size {}", bits),
+        }
+    }
+
+    fn literal(self, bits: u32, value: u128) -> String {
+        let digits = bits as usize / 4;
+        let (hi, lo) = ((value >> 64) as u64, value as u64);
+        match self {
+            Lang::C if bits == 128 => format!(
+                "((unsigned __int128)0x{:016x}u << 64 | 0x{:016x}u)",
+                hi, lo
+            ),
+            Lang::CSharp if bits == 128 => {
+                format!("new UInt128(0x{:016x}, 0x{:016x})", hi, lo)
+            }
+            Lang::C if bits == 64 => format!("0x{:0digits$x}ull", value),
+            Lang::CSharp if bits == 64 => format!("0x{:0digits$x}ul", value),
+            Lang::C | Lang::CSharp => format!("0x{:0digits$x}u", value),
+            Lang::Rust | Lang::Python => format!("0x{:0digits$x}", value),
+        }
+    }
+}
+
+/// Bits that survive the shift, a term with this mask needs no masking
+fn op_mask(bits: u32, op: &XorShiftOp) -> u128 {
+    let all = u128::MAX >> (128 - bits);
+    match *op {
+        XorShiftOp::Ident => all,
+        XorShiftOp::Shl(shl) => all >> shl,
+        XorShiftOp::Shr(shr) => (all << shr) & all,
+    }
+}
+
+/// Expression for a single `(mask, op)` term of the input `x`
+fn term(lang: Lang, bits: u32, mask: Option<u128>, op: &XorShiftOp) -> String {
+    // python integers are unbounded, left shifts always need the mask
+    let mask = match (lang, op) {
+        (Lang::Python, XorShiftOp::Shl(_)) => {
+            Some(mask.unwrap_or_else(|| op_mask(bits, op)))
+        }
+        _ => mask,
+    };
+    let masked = match mask {
+        Some(mask) => format!("(x & {})", lang.literal(bits, mask)),
+        None => "x".to_string(),
+    };
+    match *op {
+        XorShiftOp::Ident => masked,
+        XorShiftOp::Shl(shl) => format!("{} << {}", masked, shl),
+        XorShiftOp::Shr(shr) => format!("{} >> {}", masked, shr),
+    }
+}
+
+/// Function `name` applying the XOR of the `terms` to its argument, with
+/// the terms as returned by `xorshift_form` for a `bits` wide matrix
+pub fn generate<T>(
+    lang: Lang,
+    name: &str,
+    bits: u32,
+    terms: &[(Option<T>, XorShiftOp)],
+) -> String
+where
+    T: Copy + Into<u128>,
+{
+    let ty = lang.word_type(bits);
+    let exprs: Vec<String> = terms
+        .iter()
+        .map(|(mask, op)| term(lang, bits, mask.map(Into::into), op))
+        .collect();
+    let mut out = String::new();
+    match lang {
+        Lang::Rust => {
+            let body = join(&exprs, "\n        ^ ");
+            writeln!(out, "pub fn {}(x: {}) -> {} {{", name, ty, ty).unwrap();
+            writeln!(out, "    {}", body).unwrap();
+            writeln!(out, "}}").unwrap();
+        }
+        Lang::C | Lang::CSharp => {
+            let body = join(&exprs, "\n        ^ ");
+            let prefix = if lang == Lang::CSharp {
+                "public static "
+            } else {
+                ""
+            };
+            writeln!(out, "{}{} {}({} x)", prefix, ty, name, ty).unwrap();
+            writeln!(out, "{{").unwrap();
+            writeln!(out, "    return {};", body).unwrap();
+            writeln!(out, "}}").unwrap();
+        }
+        Lang::Python => {
+            writeln!(out, "def {}(x):", name).unwrap();
+            writeln!(out, "    return (").unwrap();
+            writeln!(out, "        {}", join(&exprs, "\n        ^ ")).unwrap();
+            writeln!(out, "    )").unwrap();
+        }
+    }
+    out
+}
+
+/// Function `name` moving the bits of its argument and then applying the
+/// in-place steps `x ^= op(x & mask)` in order, as returned by
+/// `xorshift_steps`
+pub fn generate_steps<T>(
+    lang: Lang,
+    name: &str,
+    bits: u32,
+    steps: &XorShiftSteps<T>,
+) -> String
+where
+    T: Copy + Into<u128>,
+{
+    let ty = lang.word_type(bits);
+    let mut out = String::new();
+    let (indent, end) = match lang {
+        Lang::Rust => {
+            writeln!(out, "pub fn {}(mut x: {}) -> {} {{", name, ty, ty)
+                .unwrap();
+            ("    ", ";")
+        }
+        Lang::C | Lang::CSharp => {
+            let prefix = if lang == Lang::CSharp {
+                "public static "
+            } else {
+                ""
+            };
+            writeln!(out, "{}{} {}({} x)", prefix, ty, name, ty).unwrap();
+            writeln!(out, "{{").unwrap();
+            ("    ", ";")
+        }
+        Lang::Python => {
+            writeln!(out, "def {}(x):", name).unwrap();
+            ("    ", "")
+        }
+    };
+    if !steps.moves_identity() {
+        let moves: Vec<String> = steps
+            .moves
+            .iter()
+            .map(|(mask, op)| term(lang, bits, mask.map(Into::into), op))
+            .collect();
+        let separator = format!("\n{}    ^ ", indent);
+        let moves = join(&moves, &separator);
+        if lang == Lang::Python && steps.moves.len() > 1 {
+            // continuation lines need the parentheses
+            writeln!(out, "{}x = ({})", indent, moves).unwrap();
+        } else {
+            writeln!(out, "{}x = {}{}", indent, moves, end).unwrap();
+        }
+    }
+    for (mask, op) in steps.steps.iter() {
+        let expr = term(lang, bits, mask.map(Into::into), op);
+        writeln!(out, "{}x ^= {}{}", indent, expr, end).unwrap();
+    }
+    match lang {
+        Lang::Rust => writeln!(out, "    x\n}}").unwrap(),
+        Lang::C | Lang::CSharp => writeln!(out, "    return x;\n}}").unwrap(),
+        Lang::Python => writeln!(out, "    return x").unwrap(),
+    }
+    out
+}
+
+fn join(exprs: &[String], separator: &str) -> String {
+    if exprs.is_empty() {
+        "0".to_string()
+    } else {
+        exprs.join(separator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lang;
+    use crate::matrix::{BitMatrix128, BitMatrix32};
+
+    #[test]
+    fn test_generate() {
+        let matrix = (BitMatrix32::eye() ^ BitMatrix32::shr(3)).and(0xfffffff0);
+        assert_eq!(
+            matrix.codegen(Lang::Rust, "step"),
+            "pub fn step(x: u32) -> u32 {\n    \
+                (x & 0xfffffff0)\n        \
+                ^ (x & 0xffffff80) >> 3\n}\n"
+        );
+        assert_eq!(
+            matrix.codegen(Lang::C, "step"),
+            "uint32_t step(uint32_t x)\n{\n    \
+                return (x & 0xfffffff0u)\n        \
+                ^ (x & 0xffffff80u) >> 3;\n}\n"
+        );
+        assert_eq!(
+            BitMatrix32::shl(5).codegen(Lang::Python, "step"),
+            "def step(x):\n    return (\n        \
+                (x & 0x07ffffff) << 5\n    )\n"
+        );
+        assert_eq!(
+            BitMatrix128::shl(5).codegen(Lang::CSharp, "Step"),
+            "public static UInt128 Step(UInt128 x)\n{\n    \
+                return x << 5;\n}\n"
+        );
+        // the inverse of `x ^ x >> 3` takes fewer in-place steps
+        let inv = (BitMatrix32::eye() ^ BitMatrix32::shr(3)).inv().unwrap();
+        assert_eq!(
+            inv.codegen(Lang::Rust, "unshift"),
+            "pub fn unshift(mut x: u32) -> u32 {\n    \
+                x ^= x >> 3;\n    \
+                x ^= x >> 6;\n    \
+                x ^= x >> 12;\n    \
+                x ^= x >> 24;\n    \
+                x\n}\n"
+        );
+        assert_eq!(
+            BitMatrix32::zero().codegen(Lang::Rust, "zero"),
+            "pub fn zero(x: u32) -> u32 {\n    0\n}\n"
+        );
+    }
+}
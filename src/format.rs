@@ -0,0 +1,509 @@
+//! Interchange formats for bit matrices.
+//!
+//! The text format is a `bitmatrix ROWSxCOLS` header followed by one line
+//! per row, holding the row as a fixed width hex number with column 0 as the
+//! lowest bit:
+//!
+//! ```text
+//! bitmatrix 3x5
+//! 01
+//! 02
+//! 14
+//! ```
+//!
+//! The binary format is the magic `GF2M` and a version byte, the row and
+//! column counts as little endian `u32`, every row as `(cols + 7) / 8` little
+//! endian bytes and finally the CRC-32 of everything before it.
+
+use crate::matrix::{BitMatrix128, BitMatrix32, BitMatrix64, Error};
+use crate::matrix_dyn::BitMatrixDyn;
+use crate::matrix_rect::{words, BitMatrix};
+use core::fmt;
+use core::str::FromStr;
+
+const MAGIC: &[u8; 4] = b"GF2M";
+const VERSION: u8 = 1;
+/// Magic, version, rows and cols
+const HEADER_LEN: usize = 13;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FormatError {
+    /// Missing or malformed `bitmatrix ROWSxCOLS` header
+    Header,
+    /// Row `row` is not the expected number of hex digits or has bits set
+    /// past the last column
+    Row {
+        row: usize,
+    },
+    /// The text has a different number of rows than the header
+    RowCount {
+        expected: usize,
+        found: usize,
+    },
+    /// Unknown magic or version
+    Magic,
+    /// The data is shorter or longer than the header says
+    Length {
+        expected: usize,
+        found: usize,
+    },
+    Checksum {
+        expected: u32,
+        found: u32,
+    },
+    /// The matrix is valid but does not fit the requested type
+    SizeMismatch {
+        rows: usize,
+        cols: usize,
+    },
+}
+
+impl BitMatrixDyn {
+    /// Serialize to the binary format, see the `format` module
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let row_bytes = self.cols().div_ceil(8);
+        let mut out =
+            Vec::with_capacity(HEADER_LEN + self.rows() * row_bytes + 4);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        for size in [self.rows(), self.cols()] {
+            let size = u32::try_from(size)
+                .expect("matrix is too large for the binary format");
+            out.extend_from_slice(&size.to_le_bytes());
+        }
+        for ii in 0..self.rows() {
+            let bytes = self.row(ii).iter().flat_map(|word| word.to_le_bytes());
+            out.extend(bytes.take(row_bytes));
+        }
+        let checksum = crc32(&out);
+        out.extend_from_slice(&checksum.to_le_bytes());
+        out
+    }
+}
+
+impl fmt::Display for BitMatrixDyn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "bitmatrix {}x{}", self.rows(), self.cols())?;
+        let digits = self.cols().div_ceil(4);
+        for ii in 0..self.rows() {
+            let row = self.row(ii);
+            for digit in (0..digits).rev() {
+                let nibble = (row[digit / 16] >> (digit % 16 * 4)) & 0xf;
+                write!(f, "{:x}", nibble)?;
+            }
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BitMatrixDyn {
+    type Err = FormatError;
+
+    fn from_str(text: &str) -> Result<Self, FormatError> {
+        let (header, body) = text.split_once('\n').unwrap_or((text, ""));
+        let (rows, cols): (usize, usize) = header
+            .trim()
+            .strip_prefix("bitmatrix ")
+            .and_then(|size| size.split_once('x'))
+            .and_then(|(rows, cols)| {
+                Some((rows.parse().ok()?, cols.parse().ok()?))
+            })
+            .ok_or(FormatError::Header)?;
+        rows.checked_mul(words(cols)).ok_or(FormatError::Header)?;
+
+        let digits = cols.div_ceil(4);
+        // rows without columns are empty lines, otherwise those are skipped
+        let lines = || {
+            body.lines()
+                .map(str::trim)
+                .filter(|text| digits == 0 || !text.is_empty())
+        };
+        // every row takes `digits` characters and a line break, the header
+        // comes from the input and must not decide the allocation alone
+        let needed = rows.checked_mul(digits + 1).ok_or(FormatError::Header)?;
+        if needed > body.len() + 1 {
+            return Err(FormatError::RowCount {
+                expected: rows,
+                found: lines().count(),
+            });
+        }
+
+        let mut out = BitMatrixDyn::zero(rows, cols);
+        let mut found = 0;
+        for text in lines() {
+            if found < rows {
+                let row = parse_row(text, digits, cols)
+                    .ok_or(FormatError::Row { row: found })?;
+                out.row_mut(found).copy_from_slice(&row);
+            }
+            found += 1;
+        }
+        if found != rows {
+            return Err(FormatError::RowCount {
+                expected: rows,
+                found,
+            });
+        }
+        Ok(out)
+    }
+}
+
+/// Words of a row given as exactly `digits` hex digits, `None` if the text
+/// is malformed or has bits set past `cols`
+fn parse_row(text: &str, digits: usize, cols: usize) -> Option<Vec<u64>> {
+    if text.len() != digits {
+        return None;
+    }
+    let mut row = vec![0; words(cols)];
+    for (digit, char) in text.chars().rev().enumerate() {
+        let nibble = char.to_digit(16)? as u64;
+        let bit = digit * 4;
+        if nibble >> cols.saturating_sub(bit).min(4) != 0 {
+            return None;
+        }
+        row[bit / 64] |= nibble << (bit % 64);
+    }
+    Some(row)
+}
+
+impl TryFrom<&[u8]> for BitMatrixDyn {
+    type Error = FormatError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, FormatError> {
+        if bytes.len() < HEADER_LEN + 4 {
+            return Err(FormatError::Length {
+                expected: HEADER_LEN + 4,
+                found: bytes.len(),
+            });
+        }
+        if bytes[..4] != MAGIC[..] || bytes[4] != VERSION {
+            return Err(FormatError::Magic);
+        }
+        let read_u32 = |at: usize| {
+            u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+        };
+        let (rows, cols) = (read_u32(5) as usize, read_u32(9) as usize);
+        let row_bytes = cols.div_ceil(8);
+        let expected = rows
+            .checked_mul(row_bytes)
+            .and_then(|len| len.checked_add(HEADER_LEN + 4))
+            .ok_or(FormatError::Header)?;
+        if bytes.len() != expected {
+            return Err(FormatError::Length {
+                expected,
+                found: bytes.len(),
+            });
+        }
+        let (expected, found) =
+            (read_u32(bytes.len() - 4), crc32(&bytes[..bytes.len() - 4]));
+        if expected != found {
+            return Err(FormatError::Checksum { expected, found });
+        }
+
+        let mut out = BitMatrixDyn::zero(rows, cols);
+        // without columns there is nothing to read, whatever the row count
+        let data = &bytes[HEADER_LEN..bytes.len() - 4];
+        for (ii, row) in data.chunks(row_bytes.max(1)).enumerate() {
+            if cols % 8 != 0 && row[row_bytes - 1] >> (cols % 8) != 0 {
+                return Err(FormatError::Row { row: ii });
+            }
+            for (jj, &byte) in row.iter().enumerate() {
+                out.row_mut(ii)[jj / 8] |= (byte as u64) << (jj % 8 * 8);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// CRC-32 as used by zlib and PNG
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Fixed size matrix from a parsed one
+fn convert<M>(matrix: BitMatrixDyn) -> Result<M, FormatError>
+where
+    M: for<'a> TryFrom<&'a BitMatrixDyn, Error = Error>,
+{
+    M::try_from(&matrix).map_err(|_| FormatError::SizeMismatch {
+        rows: matrix.rows(),
+        cols: matrix.cols(),
+    })
+}
+
+/// The formats of the fixed size matrices go through `BitMatrixDyn`
+macro_rules! impl_format {
+    ([$($gen:tt)*] $name:ty) => {
+        impl<$($gen)*> $name {
+            /// Serialize to the binary format, see the `format` module
+            pub fn to_bytes(&self) -> Vec<u8> {
+                BitMatrixDyn::from(*self).to_bytes()
+            }
+        }
+
+        impl<$($gen)*> fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&BitMatrixDyn::from(*self), f)
+            }
+        }
+
+        impl<$($gen)*> FromStr for $name {
+            type Err = FormatError;
+            fn from_str(text: &str) -> Result<Self, FormatError> {
+                convert(text.parse()?)
+            }
+        }
+
+        impl<$($gen)*> TryFrom<&[u8]> for $name {
+            type Error = FormatError;
+            fn try_from(bytes: &[u8]) -> Result<Self, FormatError> {
+                convert(BitMatrixDyn::try_from(bytes)?)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<$($gen)*> serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                BitMatrixDyn::from(*self).serialize(serializer)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de, $($gen)*> serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let matrix = BitMatrixDyn::deserialize(deserializer)?;
+                convert(matrix).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+impl_format!([] BitMatrix32);
+impl_format!([] BitMatrix64);
+impl_format!([] BitMatrix128);
+impl_format!(
+    [const R: usize, const C: usize, const W: usize] BitMatrix<R, C, W>
+);
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header => f.write_str("missing or malformed header"),
+            Self::Row { row } => write!(f, "malformed row {row}"),
+            Self::RowCount { expected, found } => {
+                write!(f, "expected {expected} rows, found {found}")
+            }
+            Self::Magic => f.write_str("unknown magic or version"),
+            Self::Length { expected, found } => {
+                write!(f, "expected {expected} bytes, found {found}")
+            }
+            Self::Checksum { expected, found } => write!(
+                f,
+                "checksum mismatch, expected {expected:08x}, found {found:08x}"
+            ),
+            Self::SizeMismatch { rows, cols } => {
+                write!(f, "a {rows}x{cols} matrix does not fit the type")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Human readable formats get the text format, the others the binary one
+#[cfg(feature = "serde")]
+mod serde_impl {
+    use super::BitMatrixDyn;
+    use core::fmt;
+    use serde::de::{self, Visitor};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    impl Serialize for BitMatrixDyn {
+        fn serialize<S: Serializer>(
+            &self,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            if serializer.is_human_readable() {
+                serializer.collect_str(self)
+            } else {
+                serializer.serialize_bytes(&self.to_bytes())
+            }
+        }
+    }
+
+    struct MatrixVisitor;
+
+    impl<'de> Visitor<'de> for MatrixVisitor {
+        type Value = BitMatrixDyn;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a bit matrix in the text or the binary format")
+        }
+
+        fn visit_str<E: de::Error>(self, text: &str) -> Result<Self::Value, E> {
+            text.parse().map_err(E::custom)
+        }
+
+        fn visit_bytes<E: de::Error>(
+            self,
+            bytes: &[u8],
+        ) -> Result<Self::Value, E> {
+            BitMatrixDyn::try_from(bytes).map_err(E::custom)
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: de::SeqAccess<'de>,
+        {
+            // formats without a byte string type store bytes as a sequence
+            let mut bytes = Vec::new();
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            self.visit_bytes(&bytes)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for BitMatrixDyn {
+        fn deserialize<D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Self, D::Error> {
+            if deserializer.is_human_readable() {
+                deserializer.deserialize_str(MatrixVisitor)
+            } else {
+                deserializer.deserialize_bytes(MatrixVisitor)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FormatError;
+    use crate::matrix::{BitMatrix128, BitMatrix32};
+    use crate::matrix_dyn::BitMatrixDyn;
+    use crate::matrix_rect::BitMatrix;
+    use crate::xorshift128::XorShift128Rng;
+
+    #[test]
+    fn test_text() {
+        let matrix =
+            BitMatrixDyn::from_rows(5, [&[0x01][..], &[0x02], &[0x14]]);
+        let text = matrix.to_string();
+        assert_eq!(text, "bitmatrix 3x5\n01\n02\n14\n");
+        assert_eq!(text.parse(), Ok(matrix));
+
+        let matrix = XorShift128Rng::build_matrix();
+        assert_eq!(matrix.to_string().parse(), Ok(matrix));
+        assert_eq!(
+            matrix.to_string().parse::<BitMatrix32>(),
+            Err(FormatError::SizeMismatch {
+                rows: 128,
+                cols: 128
+            })
+        );
+
+        let bad = |text: &str| text.parse::<BitMatrixDyn>().unwrap_err();
+        assert_eq!(
+            bad("bitmatrix 2x5"),
+            FormatError::RowCount {
+                expected: 2,
+                found: 0
+            }
+        );
+        assert_eq!(bad("bitmatrix 1x5\n20\n"), FormatError::Row { row: 0 });
+        assert_eq!(bad("bitmatrix 1x5\n1\n"), FormatError::Row { row: 0 });
+        assert_eq!(bad("matrix 1x5\n01\n"), FormatError::Header);
+        let extra = bad("bitmatrix 1x5\n01\n02\n03\n");
+        assert_eq!(
+            extra,
+            FormatError::RowCount {
+                expected: 1,
+                found: 3
+            }
+        );
+        assert_eq!(extra.to_string(), "expected 1 rows, found 3");
+
+        // headers too large to allocate for the text that follows
+        assert_eq!(
+            bad("bitmatrix 4000000000000x4000000000000\n01\n"),
+            FormatError::Header
+        );
+        assert_eq!(
+            bad("bitmatrix 100000000x100000000\n01\n"),
+            FormatError::RowCount {
+                expected: 100000000,
+                found: 1
+            }
+        );
+
+        for (rows, cols) in [(0, 5), (3, 0), (0, 0)] {
+            let matrix = BitMatrixDyn::zero(rows, cols);
+            assert_eq!(matrix.to_string().parse(), Ok(matrix.clone()));
+            let bytes = matrix.to_bytes();
+            assert_eq!(BitMatrixDyn::try_from(&bytes[..]), Ok(matrix));
+        }
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut matrix = BitMatrix::<3, 70, 2>::zero();
+        matrix.set(0, 69, true);
+        matrix.set(2, 3, true);
+        let bytes = matrix.to_bytes();
+        assert_eq!(bytes.len(), 13 + 3 * 9 + 4);
+        assert_eq!(BitMatrix::try_from(&bytes[..]), Ok(matrix));
+
+        let matrix = XorShift128Rng::build_matrix();
+        let mut bytes = matrix.to_bytes();
+        assert_eq!(BitMatrix128::try_from(&bytes[..]), Ok(matrix));
+        bytes[100] ^= 1;
+        assert!(matches!(
+            BitMatrix128::try_from(&bytes[..]),
+            Err(FormatError::Checksum { .. })
+        ));
+        assert!(matches!(
+            BitMatrix128::try_from(&bytes[..100]),
+            Err(FormatError::Length { .. })
+        ));
+
+        // no columns, so the largest row count has nothing to read
+        let mut bytes = b"GF2M\x01".to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&0_u32.to_le_bytes());
+        bytes.extend_from_slice(&super::crc32(&bytes).to_le_bytes());
+        let matrix = BitMatrixDyn::try_from(&bytes[..]).unwrap();
+        assert_eq!((matrix.rows(), matrix.cols()), (u32::MAX as usize, 0));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use serde::de::value::{BytesDeserializer, Error, StrDeserializer};
+        use serde::Deserialize;
+
+        let matrix = XorShift128Rng::build_matrix();
+        let text = matrix.to_string();
+        let parsed =
+            BitMatrix128::deserialize(StrDeserializer::<Error>::new(&text));
+        assert_eq!(parsed, Ok(matrix));
+        let bytes = matrix.to_bytes();
+        let parsed =
+            BitMatrix128::deserialize(BytesDeserializer::<Error>::new(&bytes));
+        assert_eq!(parsed, Ok(matrix));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::bitvec::BitVector;
+use crate::matrix::AffineSpace;
+
+/// What adding an equation did to the system
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The equation pinned down one more variable, the rank grew
+    Independent,
+    /// The equation follows from the earlier ones
+    Redundant,
+    /// The equation contradicts the earlier ones
+    Conflict,
+}
+
+/// Linear system over GF(2) built one equation at a time.
+///
+/// Each equation is a set of variables given as a bit vector, whose XOR is
+/// the right hand side bit. This is a single column of the matrices in the
+/// rest of the crate, see `SquareMatrix::column`. The equations are kept in
+/// reduced echelon form, so every row has a pivot variable no other row
+/// contains and the state of the system is known after every insert.
+#[derive(Clone, Debug)]
+pub struct IncrementalSolver<V> {
+    vars: usize,
+    rows: Vec<V>,
+    rhs: Vec<bool>,
+    pivots: Vec<usize>,
+    equations: usize,
+    conflict: Option<usize>,
+}
+
+impl<V: BitVector> IncrementalSolver<V> {
+    /// Solver for the first `vars` bits of `V`
+    pub fn new(vars: usize) -> Self {
+        assert!(vars <= V::BITS, "too many variables for the vector");
+        Self {
+            vars,
+            rows: Vec::new(),
+            rhs: Vec::new(),
+            pivots: Vec::new(),
+            equations: 0,
+            conflict: None,
+        }
+    }
+
+    pub fn vars(&self) -> usize {
+        self.vars
+    }
+
+    pub fn rank(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Number of equations added so far
+    pub fn equations(&self) -> usize {
+        self.equations
+    }
+
+    /// Index of the first equation that contradicted the earlier ones
+    pub fn first_conflict(&self) -> Option<usize> {
+        self.conflict
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.conflict.is_none()
+    }
+
+    /// Every variable is pinned down, there is at most one solution
+    pub fn is_determined(&self) -> bool {
+        self.rank() == self.vars
+    }
+
+    /// Variables that no equation pins down yet
+    pub fn free_vars(&self) -> Vec<usize> {
+        let mut pivot = vec![false; self.vars];
+        for &var in self.pivots.iter() {
+            pivot[var] = true;
+        }
+        (0..self.vars).filter(|&var| !pivot[var]).collect()
+    }
+
+    /// Add the equation `XOR of the vars set in coeffs == rhs`. After a
+    /// conflict the solver keeps the consistent equations and keeps
+    /// reporting the first conflict.
+    pub fn add(&mut self, coeffs: V, rhs: bool) -> Outcome {
+        assert!(
+            coeffs.shr(self.vars).is_zero(),
+            "equation uses unknown variables"
+        );
+        let index = self.equations;
+        self.equations += 1;
+
+        // the rows are fully reduced, so clearing one pivot never sets
+        // another one
+        let mut row = coeffs;
+        let mut rhs = rhs;
+        for ii in 0..self.rows.len() {
+            if row.get(self.pivots[ii]) {
+                row = row.xor(self.rows[ii]);
+                rhs ^= self.rhs[ii];
+            }
+        }
+
+        let Some(pivot) = row.first_one() else {
+            if rhs {
+                self.conflict.get_or_insert(index);
+                return Outcome::Conflict;
+            }
+            return Outcome::Redundant;
+        };
+        // keep the other rows free of the new pivot
+        for ii in 0..self.rows.len() {
+            if self.rows[ii].get(pivot) {
+                self.rows[ii] = self.rows[ii].xor(row);
+                self.rhs[ii] ^= rhs;
+            }
+        }
+        self.rows.push(row);
+        self.rhs.push(rhs);
+        self.pivots.push(pivot);
+        Outcome::Independent
+    }
+
+    /// All solutions of the equations so far, `None` after a conflict
+    pub fn solution(&self) -> Option<AffineSpace<V>> {
+        if !self.is_consistent() {
+            return None;
+        }
+        // free variables are zero in the particular solution, setting one
+        // flips the pivots of the rows that contain it
+        let mut particular = V::zero();
+        for (&pivot, &rhs) in self.pivots.iter().zip(self.rhs.iter()) {
+            particular.set(pivot, rhs);
+        }
+        let kernel = self
+            .free_vars()
+            .into_iter()
+            .map(|free| {
+                let mut vec = V::unit(free);
+                for (row, &pivot) in self.rows.iter().zip(self.pivots.iter()) {
+                    if row.get(free) {
+                        vec.set(pivot, true);
+                    }
+                }
+                vec
+            })
+            .collect();
+        Some(AffineSpace { particular, kernel })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IncrementalSolver, Outcome};
+    use crate::rand32::{
+        layout_to_state, rng_layout, rng_unknown_outputs, Rand32Ref,
+        RNG_UNKNOWNS,
+    };
+
+    #[test]
+    fn test_small_system() {
+        let mut solver = IncrementalSolver::<u32>::new(3);
+        assert_eq!(solver.add(0b011, true), Outcome::Independent);
+        assert_eq!(solver.add(0b110, false), Outcome::Independent);
+        assert_eq!(solver.free_vars(), [2]);
+        assert_eq!(solver.add(0b101, true), Outcome::Redundant);
+        let space = solver.solution().unwrap();
+        assert_eq!(space.particular, 0b001);
+        assert_eq!(space.kernel, [0b111]);
+        assert_eq!(space.iter().collect::<Vec<_>>(), [0b001, 0b110]);
+
+        assert_eq!(solver.add(0b101, false), Outcome::Conflict);
+        assert_eq!(solver.add(0b100, true), Outcome::Independent);
+        assert!(solver.is_determined());
+        assert_eq!(solver.first_conflict(), Some(3));
+        assert!(solver.solution().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown variables")]
+    fn test_unknown_variable() {
+        let mut solver = IncrementalSolver::<u128>::new(82);
+        solver.add(1 | 1 << 100, true);
+    }
+
+    #[test]
+    fn test_streaming_mod24() {
+        let rows: Vec<[u32; 40]> =
+            (0..RNG_UNKNOWNS).map(rng_unknown_outputs::<40>).collect();
+        let mut rng = Rand32Ref::seeded(0x13371337, 0xdeadbeef, 0xcafebabe);
+        let state = rng.state();
+        let mut solver = IncrementalSolver::<u128>::new(RNG_UNKNOWNS);
+        let mut values = 0;
+        while !solver.is_determined() {
+            let low_bits = (rng.rand() % 24) & 0b111;
+            for bit in 0..3 {
+                let mut coeffs = 0;
+                for (ii, outputs) in rows.iter().enumerate() {
+                    let set = (outputs[values] >> bit) & 1;
+                    coeffs |= (set as u128) << ii;
+                }
+                solver.add(coeffs, (low_bits >> bit) & 1 != 0);
+            }
+            values += 1;
+        }
+        // 88 unknowns take 30 values, the last two bits are redundant
+        assert_eq!((values, solver.equations()), (30, 90));
+        let space = solver.solution().unwrap();
+        assert_eq!(space.dim(), 0);
+        let (s1, s2, s3) = layout_to_state(&rng_layout(), space.particular);
+        // the low bits of each seed are never observed
+        assert_eq!(
+            (s1 >> 1, s2 >> 3, s3 >> 4),
+            (state.0 >> 1, state.1 >> 3, state.2 >> 4)
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use crate::bitvec::BitVector;
+use core::fmt;
+
+/// Named state field, only the `free` bits are unknowns. The rest of the
+/// bits are fixed to the values in `fixed`.
+#[derive(Clone, Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub width: u32,
+    pub free: u64,
+    pub fixed: u64,
+}
+
+impl Field {
+    pub fn new(name: &'static str, width: u32, free: u64) -> Self {
+        assert!(width <= 64, "fields are at most 64 bits wide");
+        let mask = if width == 64 { !0 } else { (1 << width) - 1 };
+        Self {
+            name,
+            width,
+            free: free & mask,
+            fixed: 0,
+        }
+    }
+
+    /// Set the values of the bits that are not free
+    pub fn fixed(mut self, fixed: u64) -> Self {
+        self.fixed = fixed & !self.free;
+        self
+    }
+}
+
+/// Single unknown, displayed as `seed2 bit 17`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Variable {
+    pub field: &'static str,
+    pub bit: u32,
+}
+
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bit {}", self.field, self.bit)
+    }
+}
+
+/// Contiguous free bits of a field, stored contiguously in the vector
+#[derive(Clone, Debug)]
+struct Run {
+    field: usize,
+    bit: u32,
+    len: u32,
+    index: usize,
+}
+
+/// Mapping between the state fields of a generator and the unknowns of a
+/// linear system. The free bits of all fields are packed into a vector in
+/// field order, from the low bits up.
+#[derive(Clone, Debug)]
+pub struct VariableLayout {
+    fields: Vec<Field>,
+    runs: Vec<Run>,
+    len: usize,
+}
+
+impl VariableLayout {
+    pub fn new(fields: Vec<Field>) -> Self {
+        let mut runs: Vec<Run> = Vec::new();
+        let mut len = 0;
+        for (field_idx, field) in fields.iter().enumerate() {
+            for bit in 0..field.width {
+                if (field.free >> bit) & 1 == 0 {
+                    continue;
+                }
+                match runs.last_mut() {
+                    Some(run)
+                        if run.field == field_idx
+                            && run.bit + run.len == bit =>
+                    {
+                        run.len += 1
+                    }
+                    _ => runs.push(Run {
+                        field: field_idx,
+                        bit,
+                        len: 1,
+                        index: len,
+                    }),
+                }
+                len += 1;
+            }
+        }
+        Self { fields, runs, len }
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Number of unknowns
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of bits in the full state, all the fields back to back
+    pub fn state_bits(&self) -> usize {
+        self.fields.iter().map(|field| field.width as usize).sum()
+    }
+
+    /// Vector index of the given state field bit, `None` if the bit is fixed
+    pub fn index_of(&self, field: &str, bit: u32) -> Option<usize> {
+        let field = self.fields.iter().position(|f| f.name == field)?;
+        self.runs
+            .iter()
+            .find(|run| {
+                run.field == field
+                    && (run.bit..run.bit + run.len).contains(&bit)
+            })
+            .map(|run| run.index + (bit - run.bit) as usize)
+    }
+
+    /// The state field bit stored at the given vector index
+    pub fn variable(&self, index: usize) -> Variable {
+        let run = self
+            .runs
+            .iter()
+            .find(|run| {
+                (run.index..run.index + run.len as usize).contains(&index)
+            })
+            .expect("variable index out of range");
+        Variable {
+            field: self.fields[run.field].name,
+            bit: run.bit + (index - run.index) as u32,
+        }
+    }
+
+    /// Position of each unknown in the full state, for picking the rows of
+    /// a matrix that acts on the full state
+    pub fn state_positions(&self) -> Vec<usize> {
+        let offsets = self.field_offsets();
+        self.runs
+            .iter()
+            .flat_map(|run| {
+                let start = offsets[run.field] + run.bit as usize;
+                start..start + run.len as usize
+            })
+            .collect()
+    }
+
+    fn field_offsets(&self) -> Vec<usize> {
+        self.fields
+            .iter()
+            .scan(0, |offset, field| {
+                let current = *offset;
+                *offset += field.width as usize;
+                Some(current)
+            })
+            .collect()
+    }
+
+    /// Pack the free bits of the field values into a vector
+    pub fn pack<V: BitVector>(&self, values: &[u64]) -> V {
+        assert_eq!(values.len(), self.fields.len(), "one value per field");
+        assert!(self.len <= V::BITS, "layout does not fit into the vector");
+        let mut vector = V::zero();
+        for run in self.runs.iter() {
+            let bits = (values[run.field] >> run.bit) & len_mask(run.len);
+            vector.xor_bits(run.index, bits);
+        }
+        vector
+    }
+
+    /// Unpack a vector into field values, filling in the fixed bits
+    pub fn unpack<V: BitVector, const N: usize>(&self, vector: V) -> [u64; N] {
+        assert_eq!(N, self.fields.len(), "one value per field");
+        assert!(self.len <= V::BITS, "layout does not fit into the vector");
+        let mut values: [u64; N] =
+            core::array::from_fn(|ii| self.fields[ii].fixed);
+        for run in self.runs.iter() {
+            let bits = vector.bits(run.index, run.len);
+            values[run.field] |= bits << run.bit;
+        }
+        values
+    }
+}
+
+fn len_mask(len: u32) -> u64 {
+    if len == 64 {
+        !0
+    } else {
+        (1 << len) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Field, VariableLayout};
+
+    #[test]
+    fn test_layout() {
+        let layout = VariableLayout::new(vec![
+            Field::new("lo", 16, 0xff0f).fixed(0x50),
+            Field::new("hi", 64, !0 << 60),
+        ]);
+        assert_eq!(layout.len(), 16);
+        assert_eq!(layout.state_bits(), 80);
+        assert_eq!(layout.index_of("lo", 3), Some(3));
+        assert_eq!(layout.index_of("lo", 4), None);
+        assert_eq!(layout.index_of("lo", 8), Some(4));
+        assert_eq!(layout.index_of("hi", 61), Some(13));
+        assert_eq!(layout.variable(13).to_string(), "hi bit 61");
+        let positions = layout.state_positions();
+        assert_eq!(positions[..5], [0, 1, 2, 3, 8]);
+        assert_eq!(positions[12..], [76, 77, 78, 79]);
+
+        let vector: u32 = layout.pack(&[0x1337, 0xdead << 48]);
+        assert_eq!(vector, 0xd137);
+        assert_eq!(layout.unpack(vector), [0x1357, 0xd << 60]);
+        let wide: [u64; 2] = layout.pack(&[0x1337, 0xdead << 48]);
+        assert_eq!(wide, [0xd137, 0]);
+        assert_eq!(layout.unpack(wide), [0x1357, 0xd << 60]);
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn test_unpack_too_narrow() {
+        let layout = VariableLayout::new(vec![Field::new("wide", 40, !0)]);
+        let _: [u64; 1] = layout.unpack(0_u32);
+    }
+}
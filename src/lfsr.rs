@@ -0,0 +1,115 @@
+use crate::poly::Poly;
+
+/// Shortest linear feedback shift register generating a bit sequence.
+///
+/// The connection polynomial `1 + c_1 x + ... + c_len x^len` describes the
+/// recurrence `s[n] = c_1 s[n - 1] ^ ... ^ c_len s[n - len]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lfsr {
+    pub len: usize,
+    pub connection: Poly,
+}
+
+impl Lfsr {
+    /// Reciprocal of the connection polynomial, this is the characteristic
+    /// polynomial of the register and matches `charpoly()` of the step
+    /// matrix when the whole state shows up in the output
+    pub fn charpoly(&self) -> Poly {
+        let mut out = Poly::zero();
+        for ii in 0..=self.len {
+            if self.connection.coeff(ii) {
+                out.set_coeff(self.len - ii, true);
+            }
+        }
+        out
+    }
+
+    /// The bit following `history`, which must hold at least `len` bits
+    pub fn next_bit(&self, history: &[bool]) -> bool {
+        assert!(history.len() >= self.len, "history is too short");
+        let last = history.len();
+        (1..=self.len)
+            .filter(|&ii| self.connection.coeff(ii))
+            .fold(false, |bit, ii| bit ^ history[last - ii])
+    }
+
+    /// Continue the sequence by `count` bits
+    pub fn extend(&self, bits: &mut Vec<bool>, count: usize) {
+        for _ in 0..count {
+            let bit = self.next_bit(bits);
+            bits.push(bit);
+        }
+    }
+}
+
+/// Berlekamp-Massey algorithm, finds the shortest LFSR producing `bits`.
+///
+/// The result is only unique when there are at least `2 * len` bits, so
+/// feed it twice as many bits as the expected state size.
+pub fn berlekamp_massey(bits: &[bool]) -> Lfsr {
+    let mut connection = Poly::one();
+    // connection before the last length change
+    let mut prev = Poly::one();
+    let mut len = 0;
+    // steps since the last length change
+    let mut shift = 1;
+    for (nn, &bit) in bits.iter().enumerate() {
+        let discrepancy = (1..=len)
+            .filter(|&ii| connection.coeff(ii))
+            .fold(bit, |acc, ii| acc ^ bits[nn - ii]);
+        if !discrepancy {
+            shift += 1;
+            continue;
+        }
+        let next = &connection + &prev.shl(shift);
+        if 2 * len <= nn {
+            len = nn + 1 - len;
+            prev = core::mem::replace(&mut connection, next);
+            shift = 1;
+        } else {
+            connection = next;
+            shift += 1;
+        }
+    }
+    Lfsr { len, connection }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::berlekamp_massey;
+    use crate::poly::Poly;
+    use crate::rand32::{rng_matrix, Rand32Ref};
+
+    #[test]
+    fn test_known_lfsr() {
+        // s[n] = s[n - 3] ^ s[n - 5], connection 1 + x^3 + x^5
+        let mut bits = vec![true, false, false, true, true];
+        for nn in 5..40 {
+            bits.push(bits[nn - 3] ^ bits[nn - 5]);
+        }
+        let lfsr = berlekamp_massey(&bits);
+        assert_eq!(lfsr.len, 5);
+        assert_eq!(lfsr.connection, Poly::from_words(&[0b101001]));
+        assert_eq!(lfsr.charpoly(), Poly::from_words(&[0b100101]));
+
+        let mut extended = bits[..10].to_vec();
+        lfsr.extend(&mut extended, 30);
+        assert_eq!(extended, bits);
+    }
+
+    #[test]
+    fn test_rand32_low_bit() {
+        let mut rng = Rand32Ref::new(0x13371337);
+        let bits: Vec<bool> = (0..200).map(|_| rng.rand() & 1 != 0).collect();
+        let lfsr = berlekamp_massey(&bits);
+        assert_eq!(lfsr.len, 88);
+
+        // the shifted out low bits of each field add a factor of x to the
+        // minimal polynomial, which does not show up in the outputs
+        let expected = rng_matrix()
+            .iter()
+            .map(|matrix| &matrix.minpoly() / &Poly::x())
+            .fold(Poly::one(), |acc, poly| &acc * &poly);
+        assert_eq!(lfsr.charpoly(), expected);
+    }
+}
@@ -1,13 +1,28 @@
 #![cfg_attr(feature = "simd", feature(portable_simd))]
 
+pub mod affine;
+pub mod bitvec;
+pub mod codegen;
+pub mod format;
+pub mod incremental;
+pub mod layout;
+pub mod lfsr;
 pub mod matrix;
+pub mod matrix_dyn;
+pub mod matrix_rect;
+pub mod matrix_sparse;
 pub mod mod24;
+pub mod period;
+pub mod poly;
+pub mod power;
 pub use mod24::Mod24Solver;
 pub mod rand32;
 pub mod rand32_rev;
 #[cfg(feature = "simd")]
 pub mod rand32_simd;
+mod splitmix;
 
+pub mod sym;
 pub mod u56_to_seed;
 pub use u56_to_seed::U56ToSeed;
 
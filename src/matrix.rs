@@ -1,8 +1,13 @@
+use crate::bitvec::BitVector;
+use crate::codegen::{self, Lang};
+use crate::matrix_dyn::BitMatrixDyn;
+use crate::poly::Poly;
+use crate::splitmix::splitmix64;
 use core::fmt;
 #[cfg(feature = "simd")]
 use core::simd::{LaneCount, Simd, SupportedLaneCount};
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum XorShiftOp {
     Ident,
     Shr(u32),
@@ -12,6 +17,139 @@ pub enum XorShiftOp {
 #[derive(Debug)]
 pub enum Error {
     MatrixNotInvertible { stuck_at_col: usize },
+    /// Equation (column) `equation` contradicts the ones before it, while
+    /// all the equations before it are consistent
+    NoSolution { equation: usize },
+    SizeMismatch { rows: usize, cols: usize },
+    /// The equations only determine `rank` of the unknowns
+    Underdetermined { rank: usize },
+}
+
+/// Set of all solutions of a linear system: `particular` xor any combination
+/// of the `kernel` basis vectors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AffineSpace<T> {
+    pub particular: T,
+    pub kernel: Vec<T>,
+}
+
+impl<T> AffineSpace<T> {
+    /// Dimension of the solution space, there are `2^dim` solutions
+    pub fn dim(&self) -> usize {
+        self.kernel.len()
+    }
+}
+
+impl<T: BitVector> AffineSpace<T> {
+    /// Enumerate every solution, consecutive items differ by one kernel
+    /// vector (gray code order)
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        assert!(self.dim() < usize::BITS as usize, "too many solutions");
+        let mut current = self.particular;
+        (0..1_usize << self.dim()).map(move |ii| {
+            if ii != 0 {
+                let next = self.kernel[ii.trailing_zeros() as usize];
+                current = current.xor(next);
+            }
+            current
+        })
+    }
+}
+
+/// Square matrix over a `BitVector`, so that code can be written once for
+/// every size. Row `ii` is the output of the input bit `ii`.
+pub trait SquareMatrix: Copy {
+    type Vector: BitVector;
+
+    fn from_rows(f: impl FnMut(usize) -> Self::Vector) -> Self;
+
+    fn row(&self, ii: usize) -> Self::Vector;
+
+    fn mul_vec(&self, vec: Self::Vector) -> Self::Vector;
+
+    /// The inputs that output bit `col` depends on, a single equation of
+    /// the system `vec * self == rhs`
+    fn column(&self, col: usize) -> Self::Vector {
+        let mut column = Self::Vector::zero();
+        for ii in 0..Self::Vector::BITS {
+            column.set(ii, self.row(ii).get(col));
+        }
+        column
+    }
+}
+
+/// Result of probing a closure for linearity over GF(2)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Linearity<T> {
+    /// `f(x ^ y) == f(x) ^ f(y)` held for every probe
+    Linear,
+    /// `f(x) ^ constant` is linear
+    Affine { constant: T },
+    /// The output bits that broke linearity on some probe
+    Nonlinear { bits: T },
+}
+
+/// A matrix as `x = op(x & mask) ^ ...` over the `moves` terms, followed
+/// by the in-place `steps` `x ^= op(x & mask)` applied in order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XorShiftSteps<T> {
+    pub moves: Vec<(Option<T>, XorShiftOp)>,
+    pub steps: Vec<(Option<T>, XorShiftOp)>,
+}
+
+impl<T> XorShiftSteps<T> {
+    /// The moves can be skipped when they leave every bit in place
+    pub fn moves_identity(&self) -> bool {
+        matches!(self.moves.as_slice(), [(None, XorShiftOp::Ident)])
+    }
+
+    /// Number of shift and xor operations
+    pub fn len(&self) -> usize {
+        let moves = if self.moves_identity() {
+            0
+        } else {
+            self.moves.len()
+        };
+        moves + self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Reduced row echelon form along with the combinations of the original rows
+/// that produced each reduced row
+struct Echelon<T, const N: usize> {
+    rows: [T; N],
+    comb: [T; N],
+    pivots: [u32; N],
+    rank: usize,
+}
+
+/// All 256 xor combinations of 8 rows, entry `ii` is the xor of the rows
+/// selected by the bits of `ii`. Filled in gray code order, so every entry
+/// costs a single xor.
+fn gray_table<T>(rows: &[T]) -> [T; 256]
+where
+    T: Copy + Default + core::ops::BitXor<Output = T>,
+{
+    let mut table = [T::default(); 256];
+    let mut prev = 0;
+    for ii in 1_usize..256 {
+        let gray = ii ^ (ii >> 1);
+        table[gray] = table[prev] ^ rows[ii.trailing_zeros() as usize];
+        prev = gray;
+    }
+    table
+}
+
+/// For each byte of the input, the XOR of the rows selected by each of its
+/// 256 values. Multiplying a vector then costs one lookup per byte instead
+/// of one conditional XOR per row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatrixTable<T> {
+    tables: Vec<[T; 256]>,
 }
 
 macro_rules! impl_bitmatrix {
@@ -36,6 +174,25 @@ macro_rules! impl_bitmatrix {
                 out
             }
         }
+
+        #[cfg(feature = "simd")]
+        impl<const LANES: usize> core::ops::Mul<&MatrixTable<$ty>>
+            for Simd<$ty, LANES>
+        where
+            LaneCount<LANES>: SupportedLaneCount,
+        {
+            type Output = Simd<$ty, LANES>;
+            fn mul(self, table: &MatrixTable<$ty>) -> Self::Output {
+                let s = Simd::splat;
+                let mut out = s(0);
+                for (ii, table) in table.tables.iter().enumerate() {
+                    let bytes = (self >> s((ii * 8) as $ty)) & s(0xff);
+                    let bytes = core::simd::num::SimdUint::cast(bytes);
+                    out ^= Simd::gather_or_default(table, bytes);
+                }
+                out
+            }
+        }
     };
 
     ($name:ident, $ty:ty, $fmt_sz:expr) => {
@@ -80,6 +237,21 @@ macro_rules! impl_bitmatrix {
                 Self(arr)
             }
 
+            /// Multiply each of `vecs` by the matrix into `out`. A table of
+            /// all combinations of each 8 rows is built first, after that
+            /// every vector takes one lookup per byte instead of one step
+            /// per bit, which pays off from a few dozen vectors on.
+            pub fn mul_many(&self, vecs: &[$ty], out: &mut [$ty]) {
+                self.table().mul_many(vecs, out)
+            }
+
+            /// Precompute the byte tables for repeated multiplies
+            pub fn table(&self) -> MatrixTable<$ty> {
+                MatrixTable {
+                    tables: self.0.chunks(8).map(gray_table).collect(),
+                }
+            }
+
             pub fn pow(&self, mut pow: usize) -> Self {
                 let mut mul = *self;
                 let mut out = Self::eye();
@@ -155,6 +327,68 @@ macro_rules! impl_bitmatrix {
                 lower ^ higher
             }
 
+            /// Transpose by recursively swapping the off-diagonal blocks,
+            /// all the blocks of the same size are swapped at once
+            pub fn transpose(mut self) -> Self {
+                let arr = &mut self.0;
+                let mut width = $name::BITS / 2;
+                while width > 0 {
+                    // bits that are in the lower half of their block
+                    let mask = !0 / ((1 << width) + 1);
+                    for ii in 0..$name::BITS {
+                        if ii & width != 0 {
+                            continue;
+                        }
+                        let swap =
+                            ((arr[ii] >> width) ^ arr[ii + width]) & mask;
+                        arr[ii + width] ^= swap;
+                        arr[ii] ^= swap << width;
+                    }
+                    width /= 2;
+                }
+                self
+            }
+
+            /// Column `col` as a vector, bit `ii` is taken from row `ii`
+            pub fn col(&self, col: usize) -> $ty {
+                let mut out = 0;
+                for (ii, row) in self.0.iter().enumerate() {
+                    out |= ((row >> col) & 1) << ii;
+                }
+                out
+            }
+
+            pub fn set_col(&mut self, col: usize, value: $ty) {
+                for (ii, row) in self.0.iter_mut().enumerate() {
+                    *row &= !(1 << col);
+                    *row |= ((value >> ii) & 1) << col;
+                }
+            }
+
+            /// Rearrange rows, row `ii` of the result is row `rows[ii]` of
+            /// `self`. Rows past `rows.len()` are zero.
+            pub fn permute_rows(self, rows: &[usize]) -> Self {
+                assert!(rows.len() <= $name::BITS, "too many rows");
+                let mut out = Self::zero();
+                for (dst, &src) in out.0.iter_mut().zip(rows) {
+                    *dst = self.0[src];
+                }
+                out
+            }
+
+            /// Rearrange columns, column `ii` of the result is column
+            /// `cols[ii]` of `self`. Columns past `cols.len()` are zero, so
+            /// this can also be used to select a subset of the columns.
+            pub fn permute_cols(self, cols: &[usize]) -> Self {
+                assert!(cols.len() <= $name::BITS, "too many columns");
+                let transposed = self.transpose();
+                let mut out = Self::zero();
+                for (dst, &src) in out.0.iter_mut().zip(cols) {
+                    *dst = transposed.0[src];
+                }
+                out.transpose()
+            }
+
             pub fn shr(bits: u32) -> Self {
                 Self::eye() >> bits
             }
@@ -163,44 +397,212 @@ macro_rules! impl_bitmatrix {
                 Self::eye() << bits
             }
 
+            /// Plain Gauss-Jordan inversion usable in constants, far slower
+            /// than `inv` at runtime
+            pub const fn const_inv(&self) -> Result<Self, Error> {
+                let mut tmp = self.0;
+                let mut inv = [0; $name::BITS];
+                let mut ii = 0;
+                while ii < $name::BITS {
+                    inv[ii] = 1 << ii;
+                    ii += 1;
+                }
+                let mut col = 0;
+                while col < $name::BITS {
+                    let bit: $ty = 1 << col;
+                    let mut pivot = col;
+                    while pivot < $name::BITS && tmp[pivot] & bit == 0 {
+                        pivot += 1;
+                    }
+                    if pivot == $name::BITS {
+                        return Err(Error::MatrixNotInvertible {
+                            stuck_at_col: col,
+                        });
+                    }
+                    (tmp[col], tmp[pivot]) = (tmp[pivot], tmp[col]);
+                    (inv[col], inv[pivot]) = (inv[pivot], inv[col]);
+                    let mut row = 0;
+                    while row < $name::BITS {
+                        if row != col && tmp[row] & bit != 0 {
+                            tmp[row] ^= tmp[col];
+                            inv[row] ^= inv[col];
+                        }
+                        row += 1;
+                    }
+                    col += 1;
+                }
+                Ok(Self(inv))
+            }
+
+            /// Invert the matrix using the method of four russians: pivots
+            /// are found for 8 columns at a time, then every other row is
+            /// cleared with a single lookup into a table of pivot row
+            /// combinations
             pub fn inv(&self) -> Result<Self, Error> {
                 let mut tmp = self.0;
                 let mut inv = Self::eye().0;
 
-                // reduce to triangular form
-                for ii in 0..$name::BITS {
-                    // find first non-zero start, swap rows if neede
-                    let mask = 1 << ii;
-                    if tmp[ii] & mask == 0 {
-                        let pos = (ii + 1..$name::BITS)
-                            .find(|&jj| tmp[jj] & mask != 0)
-                            .ok_or(Error::MatrixNotInvertible {
-                                stuck_at_col: ii,
-                            })?;
+                for start in (0..$name::BITS).step_by(8) {
+                    let block = start..start + 8;
+                    for ii in block.clone() {
+                        // find a row with this bit set, reducing the
+                        // candidates by the pivots already in this block
+                        let mut pos = None;
+                        for jj in ii..$name::BITS {
+                            for kk in start..ii {
+                                if (tmp[jj] >> kk) & 1 != 0 {
+                                    tmp[jj] ^= tmp[kk];
+                                    inv[jj] ^= inv[kk];
+                                }
+                            }
+                            if (tmp[jj] >> ii) & 1 != 0 {
+                                pos = Some(jj);
+                                break;
+                            }
+                        }
+                        let pos = pos.ok_or(Error::MatrixNotInvertible {
+                            stuck_at_col: ii,
+                        })?;
                         tmp.swap(ii, pos);
                         inv.swap(ii, pos);
+
+                        // keep the block pivots reduced with each other
+                        for kk in start..ii {
+                            if (tmp[kk] >> ii) & 1 != 0 {
+                                tmp[kk] ^= tmp[ii];
+                                inv[kk] ^= inv[ii];
+                            }
+                        }
                     }
 
-                    // clear this bit from the remaining rows
-                    for jj in (ii + 1)..$name::BITS {
-                        if (tmp[jj] >> ii) & 1 != 0 {
-                            tmp[jj] ^= tmp[ii];
-                            inv[jj] ^= inv[ii];
+                    // clear the block columns from all other rows
+                    let tmp_table = gray_table(&tmp[block.clone()]);
+                    let inv_table = gray_table(&inv[block.clone()]);
+                    for jj in 0..$name::BITS {
+                        if block.contains(&jj) {
+                            continue;
                         }
+                        let idx = ((tmp[jj] >> start) & 0xff) as usize;
+                        tmp[jj] ^= tmp_table[idx];
+                        inv[jj] ^= inv_table[idx];
                     }
                 }
 
-                // reduce to diagonal
-                for ii in 0..$name::BITS {
-                    for jj in (ii + 1)..$name::BITS {
-                        if (tmp[ii] >> jj) & 1 != 0 {
-                            tmp[ii] ^= tmp[jj];
-                            inv[ii] ^= inv[jj];
+                Ok(Self(inv))
+            }
+
+            /// Reduced row echelon form of the matrix, rows past the rank
+            /// are zero and their combinations span the kernel
+            fn echelon(&self) -> Echelon<$ty, { $name::BITS }> {
+                let mut rows = self.0;
+                let mut comb = Self::eye().0;
+                let mut pivots = [0; $name::BITS];
+                let mut rank = 0;
+                for col in 0..$name::BITS {
+                    let mask = 1 << col;
+                    let Some(pos) =
+                        (rank..$name::BITS).find(|&jj| rows[jj] & mask != 0)
+                    else {
+                        continue;
+                    };
+                    rows.swap(rank, pos);
+                    comb.swap(rank, pos);
+                    for jj in 0..$name::BITS {
+                        if jj != rank && rows[jj] & mask != 0 {
+                            rows[jj] ^= rows[rank];
+                            comb[jj] ^= comb[rank];
                         }
                     }
+                    pivots[rank] = col as u32;
+                    rank += 1;
                 }
+                Echelon {
+                    rows,
+                    comb,
+                    pivots,
+                    rank,
+                }
+            }
 
-                Ok(Self(inv))
+            pub fn rank(&self) -> usize {
+                self.echelon().rank
+            }
+
+            /// Basis of all vectors `x` such that `x * self == 0`
+            pub fn kernel(&self) -> Vec<$ty> {
+                let ech = self.echelon();
+                ech.comb[ech.rank..].to_vec()
+            }
+
+            /// Find all vectors `x` such that `x * self == rhs`. The
+            /// pivots are taken in column order, so whatever is left of
+            /// `rhs` after the reduction is the equations that contradict
+            /// earlier ones.
+            pub fn solve(&self, rhs: $ty) -> Result<AffineSpace<$ty>, Error> {
+                let ech = self.echelon();
+                let mut rest = rhs;
+                let mut particular = 0;
+                for ii in 0..ech.rank {
+                    if (rest >> ech.pivots[ii]) & 1 != 0 {
+                        rest ^= ech.rows[ii];
+                        particular ^= ech.comb[ii];
+                    }
+                }
+                if rest != 0 {
+                    return Err(Error::NoSolution {
+                        equation: rest.trailing_zeros() as usize,
+                    });
+                }
+                Ok(AffineSpace {
+                    particular,
+                    kernel: ech.comb[ech.rank..].to_vec(),
+                })
+            }
+
+            /// Recover the matrix of a linear closure by probing it on the
+            /// basis vectors. An affine closure gives its linear part.
+            pub fn from_fn(f: impl Fn($ty) -> $ty) -> Self {
+                let constant = f(0);
+                Self(core::array::from_fn(|ii| f(1 << ii) ^ constant))
+            }
+
+            /// Check `f(x ^ y) ^ f(0) == f(x) ^ f(y)` on `probes` random
+            /// pairs of inputs
+            pub fn check_linearity(
+                f: impl Fn($ty) -> $ty,
+                probes: usize,
+            ) -> Linearity<$ty> {
+                let mut seed = probes as u64;
+                let mut random = || {
+                    let lo = splitmix64(&mut seed) as u128;
+                    (lo | (splitmix64(&mut seed) as u128) << 64) as $ty
+                };
+                let constant = f(0);
+                let mut bits = 0;
+                for _ in 0..probes {
+                    let (xx, yy) = (random(), random());
+                    bits |= f(xx ^ yy) ^ f(xx) ^ f(yy) ^ constant;
+                }
+                if bits != 0 {
+                    Linearity::Nonlinear { bits }
+                } else if constant != 0 {
+                    Linearity::Affine { constant }
+                } else {
+                    Linearity::Linear
+                }
+            }
+
+            pub fn charpoly(&self) -> Poly {
+                BitMatrixDyn::from(*self).charpoly()
+            }
+
+            pub fn minpoly(&self) -> Poly {
+                BitMatrixDyn::from(*self).minpoly()
+            }
+
+            /// `poly(self)`, e.g. `x^n mod charpoly` evaluates to `self^n`
+            pub fn eval_poly(&self, poly: &Poly) -> Self {
+                poly.apply(Self::eye(), |acc| acc * *self)
             }
 
             pub fn xorshift_form(&self) -> Vec<(Option<$ty>, XorShiftOp)> {
@@ -240,6 +642,202 @@ macro_rules! impl_bitmatrix {
                 }
                 result
             }
+
+            /// Matrix of `op(x & mask)`
+            fn term_matrix(mask: $ty, op: XorShiftOp) -> Self {
+                let mut out = Self::zero();
+                for ii in 0..$name::BITS {
+                    let bit: $ty = 1 << ii;
+                    out.0[ii] = match op {
+                        XorShiftOp::Ident => bit,
+                        XorShiftOp::Shl(shl) => bit << shl,
+                        XorShiftOp::Shr(shr) => bit >> shr,
+                    } & if mask & bit != 0 { !0 } else { 0 };
+                }
+                out
+            }
+
+            /// Rows with a bit set on the diagonal of `op`, this is the mask
+            /// of the single term covering that diagonal
+            fn diagonal(&self, op: XorShiftOp) -> $ty {
+                let mut mask = 0;
+                for (ii, row) in self.0.iter().enumerate() {
+                    let col = match op {
+                        XorShiftOp::Ident => Some(ii),
+                        XorShiftOp::Shl(shl) => Some(ii + shl as usize)
+                            .filter(|&col| col < $name::BITS),
+                        XorShiftOp::Shr(shr) => ii.checked_sub(shr as usize),
+                    };
+                    if col.is_some_and(|col| (row >> col) & 1 != 0) {
+                        mask |= 1 << ii;
+                    }
+                }
+                mask
+            }
+
+            fn all_ops() -> impl Iterator<Item = XorShiftOp> {
+                let shifts = 1..$name::BITS as u32;
+                core::iter::once(XorShiftOp::Ident)
+                    .chain(shifts.clone().map(XorShiftOp::Shr))
+                    .chain(shifts.map(XorShiftOp::Shl))
+            }
+
+            fn some_mask(mask: $ty, op: XorShiftOp) -> Option<$ty> {
+                Some(mask).filter(|&mask| mask != $name::op_mask(&op))
+            }
+
+            /// Rebuild a matrix from the terms of `xorshift_form`, the XOR
+            /// of `op(x & mask)` for every term
+            pub fn from_xorshift_form(
+                terms: &[(Option<$ty>, XorShiftOp)],
+            ) -> Self {
+                terms.iter().fold(Self::zero(), |acc, &(mask, op)| {
+                    let mask = mask.unwrap_or($name::op_mask(&op));
+                    acc ^ Self::term_matrix(mask, op)
+                })
+            }
+
+            /// Same as `xorshift_form` with a single term for every shift.
+            /// Each term covers one diagonal of the matrix, so this is the
+            /// smallest number of terms.
+            pub fn xorshift_form_min(&self) -> Vec<(Option<$ty>, XorShiftOp)> {
+                $name::all_ops()
+                    .filter_map(|op| {
+                        let mask = self.diagonal(op);
+                        (mask != 0).then(|| ($name::some_mask(mask, op), op))
+                    })
+                    .collect()
+            }
+
+            /// Decompose a unit triangular matrix into in-place steps
+            /// `x ^= op(x & mask)`, clearing the lowest shift left over
+            fn triangular_steps(
+                mut matrix: Self,
+                steps: &mut Vec<(Option<$ty>, XorShiftOp)>,
+            ) {
+                while matrix != Self::eye() {
+                    let (mask, op) = $name::all_ops()
+                        .skip(1)
+                        .map(|op| (matrix.diagonal(op), op))
+                        .filter(|&(mask, _)| mask != 0)
+                        .min_by_key(|&(_, op)| match op {
+                            XorShiftOp::Shl(bits) | XorShiftOp::Shr(bits) => {
+                                bits
+                            }
+                            XorShiftOp::Ident => 0,
+                        })
+                        .unwrap();
+                    let step = Self::eye() ^ Self::term_matrix(mask, op);
+                    // the inverse only adds multiples of the shift, which
+                    // are all above the cleared one
+                    matrix = step.inv().unwrap() * matrix;
+                    steps.push(($name::some_mask(mask, op), op));
+                }
+            }
+
+            /// Express the matrix as bit moves followed by in-place steps.
+            ///
+            /// Zero rows and columns are masked out by the moves and by a
+            /// final `Ident` step. The rest is eliminated with row pivoting
+            /// into `Q * L * U`, where the pivot permutation `Q` becomes the
+            /// moves and the unit triangular `L` and `U` become steps with
+            /// right and left shifts. Returns `None` when the rest of the
+            /// matrix is singular.
+            pub fn xorshift_steps(&self) -> Option<XorShiftSteps<$ty>> {
+                // fill the diagonal of the zero rows and columns
+                let (mut ignored, mut cleared): ($ty, $ty) = (0, 0);
+                let mut rest = *self;
+                for ii in 0..$name::BITS {
+                    if self.0[ii] == 0 {
+                        ignored |= 1 << ii;
+                        rest.0[ii] |= 1 << ii;
+                    }
+                    if self.col(ii) == 0 {
+                        cleared |= 1 << ii;
+                        rest.0[ii] |= 1 << ii;
+                    }
+                }
+
+                let mut perm: [usize; $name::BITS] =
+                    core::array::from_fn(|ii| ii);
+                let mut lower = Self::eye();
+                let mut used = [0_u32; 2 * $name::BITS];
+                for col in 0..$name::BITS {
+                    // keep the moves on as few shifts as possible
+                    let pivot = (col..$name::BITS)
+                        .filter(|&row| (rest.0[row] >> col) & 1 != 0)
+                        .min_by_key(|&row| {
+                            let shift = perm[row] + $name::BITS - col;
+                            (
+                                core::cmp::Reverse(used[shift]),
+                                perm[row].abs_diff(col),
+                            )
+                        })?;
+                    used[perm[pivot] + $name::BITS - col] += 1;
+                    rest.0.swap(col, pivot);
+                    perm.swap(col, pivot);
+                    // swap the multipliers found so far, keeping the diagonal
+                    let below = (1 << col) - 1;
+                    let (lo_col, lo_pivot) =
+                        (lower.0[col] & below, lower.0[pivot] & below);
+                    lower.0[col] ^= lo_col ^ lo_pivot;
+                    lower.0[pivot] ^= lo_col ^ lo_pivot;
+                    for row in col + 1..$name::BITS {
+                        if (rest.0[row] >> col) & 1 != 0 {
+                            rest.0[row] ^= rest.0[col];
+                            lower.0[row] |= 1 << col;
+                        }
+                    }
+                }
+
+                // input bit perm[ii] moves to bit ii, ignored bits are dropped
+                let mut moves = Self::zero();
+                for (ii, &src) in perm.iter().enumerate() {
+                    if (ignored >> src) & 1 == 0 {
+                        moves.0[src] = 1 << ii;
+                    }
+                }
+                let mut steps = Vec::new();
+                Self::triangular_steps(lower, &mut steps);
+                Self::triangular_steps(rest, &mut steps);
+                let mut out = XorShiftSteps {
+                    moves: moves.xorshift_form_min(),
+                    steps,
+                };
+                if Self::from_xorshift_steps(&out) != *self {
+                    out.steps.push((Some(cleared), XorShiftOp::Ident));
+                }
+                Some(out)
+            }
+
+            /// Rebuild a matrix from the output of `xorshift_steps`
+            pub fn from_xorshift_steps(steps: &XorShiftSteps<$ty>) -> Self {
+                let moves = Self::from_xorshift_form(&steps.moves);
+                steps.steps.iter().fold(moves, |acc, &(mask, op)| {
+                    let mask = mask.unwrap_or($name::op_mask(&op));
+                    acc * (Self::eye() ^ Self::term_matrix(mask, op))
+                })
+            }
+
+            /// Source of a function `name` applying the matrix to its
+            /// argument. Uses the in-place steps when they take fewer
+            /// operations than the XOR of the terms.
+            pub fn codegen(&self, lang: Lang, name: &str) -> String {
+                let bits = $name::BITS as u32;
+                let terms = self.xorshift_form_min();
+                match self.xorshift_steps() {
+                    Some(steps) if steps.len() < terms.len() => {
+                        codegen::generate_steps(lang, name, bits, &steps)
+                    }
+                    _ => codegen::generate(lang, name, bits, &terms),
+                }
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self::zero()
+            }
         }
 
         impl core::ops::BitXor for $name {
@@ -252,8 +850,16 @@ macro_rules! impl_bitmatrix {
 
         impl core::ops::Mul for $name {
             type Output = $name;
+            /// Method of four russians: each byte of a row selects one of
+            /// 256 precomputed combinations of the corresponding 8 rows
             fn mul(self, other: Self) -> Self::Output {
-                let arr = core::array::from_fn(|ii| self.0[ii] * other);
+                let mut arr = [0; $name::BITS];
+                for start in (0..$name::BITS).step_by(8) {
+                    let table = gray_table(&other.0[start..start + 8]);
+                    for (out, row) in arr.iter_mut().zip(self.0) {
+                        *out ^= table[((row >> start) & 0xff) as usize];
+                    }
+                }
                 $name(arr)
             }
         }
@@ -272,6 +878,48 @@ macro_rules! impl_bitmatrix {
             }
         }
 
+        impl SquareMatrix for $name {
+            type Vector = $ty;
+
+            fn from_rows(f: impl FnMut(usize) -> $ty) -> Self {
+                Self(core::array::from_fn(f))
+            }
+
+            fn row(&self, ii: usize) -> $ty {
+                self.0[ii]
+            }
+
+            fn mul_vec(&self, vec: $ty) -> $ty {
+                vec * *self
+            }
+        }
+
+        impl MatrixTable<$ty> {
+            /// `vec * matrix` for the matrix the table was built from
+            pub fn mul(&self, vec: $ty) -> $ty {
+                let mut out = 0;
+                for (ii, table) in self.tables.iter().enumerate() {
+                    out ^= table[((vec >> (ii * 8)) & 0xff) as usize];
+                }
+                out
+            }
+
+            /// Multiply each of `vecs` into `out`, which must be as long
+            pub fn mul_many(&self, vecs: &[$ty], out: &mut [$ty]) {
+                assert_eq!(vecs.len(), out.len(), "output size must match");
+                for (dst, &vec) in out.iter_mut().zip(vecs) {
+                    *dst = self.mul(vec);
+                }
+            }
+        }
+
+        impl core::ops::Mul<&MatrixTable<$ty>> for $ty {
+            type Output = $ty;
+            fn mul(self, table: &MatrixTable<$ty>) -> Self::Output {
+                table.mul(self)
+            }
+        }
+
         impl core::ops::Shr<u32> for $name {
             type Output = $name;
             fn shr(self, bits: u32) -> Self::Output {
@@ -294,6 +942,27 @@ impl_bitmatrix!(BitMatrix32, u32, 32, @simd);
 impl_bitmatrix!(BitMatrix64, u64, 64, @simd);
 impl_bitmatrix!(BitMatrix128, u128, 128);
 
+/// There are no 128-bit lanes, the vectors are split into the low and the
+/// high 64 bits
+#[cfg(feature = "simd")]
+impl<const LANES: usize> core::ops::Mul<BitMatrix128> for [Simd<u64, LANES>; 2]
+where
+    LaneCount<LANES>: SupportedLaneCount,
+{
+    type Output = [Simd<u64, LANES>; 2];
+    fn mul(self, matrix: BitMatrix128) -> Self::Output {
+        let s = Simd::splat;
+        let (mut lo, mut hi) = (s(0), s(0));
+        for (ii, row) in matrix.0.into_iter().enumerate() {
+            let vec = self[ii / 64] >> s(ii as u64 % 64);
+            let bit = s(0) - (vec & s(1));
+            lo ^= s(row as u64) & bit;
+            hi ^= s((row >> 64) as u64) & bit;
+        }
+        [lo, hi]
+    }
+}
+
 #[test]
 fn test_inv() {
     use BitMatrix64 as M;
@@ -301,3 +970,242 @@ fn test_inv() {
     let inv = matrix.inv().unwrap();
     assert_eq!(0x1337133713371337 * matrix * inv, 0x1337133713371337);
 }
+
+#[test]
+fn test_mul_inv() {
+    use BitMatrix128 as M;
+    let matrix = crate::xorshift128::XorShift128Rng::build_matrix();
+    // plain row by row product
+    let naive = |a: M, b: M| M(core::array::from_fn(|ii| a.0[ii] * b));
+    let square = naive(matrix, matrix);
+    assert_eq!(matrix * matrix, square);
+    assert_eq!(matrix.pow(3), naive(square, matrix));
+    let inv = matrix.inv().unwrap();
+    assert_eq!(inv * matrix, M::eye());
+    assert_eq!(matrix * inv, M::eye());
+    assert!(matches!(
+        M::shl(1).inv(),
+        Err(Error::MatrixNotInvertible { stuck_at_col: 0 })
+    ));
+}
+
+#[test]
+fn test_transpose_cols() {
+    use BitMatrix64 as M;
+    let matrix = crate::xorshift128::XorShift128Rng::build_matrix();
+    let transposed = matrix.transpose();
+    for ii in 0..128 {
+        assert_eq!(transposed.0[ii], matrix.col(ii));
+    }
+    assert_eq!(transposed.transpose(), matrix);
+
+    let mut matrix = M::eye() ^ M::shl(5);
+    assert_eq!(matrix.col(5), 0b100001);
+    matrix.set_col(5, 0x1337);
+    assert_eq!(matrix.col(5), 0x1337);
+    let swapped = matrix.permute_cols(&[5, 0]);
+    assert_eq!(swapped.col(0), 0x1337);
+    assert_eq!(swapped.col(1), 1);
+    assert_eq!(swapped.col(2), 0);
+}
+
+#[test]
+fn test_rank_kernel() {
+    use BitMatrix32 as M;
+    // low 4 bits are shifted out
+    let matrix = M::shr(4);
+    assert_eq!(matrix.rank(), 28);
+    let kernel = matrix.kernel();
+    assert_eq!(kernel.len(), 4);
+    for vec in kernel {
+        assert_eq!(vec * matrix, 0);
+        assert_eq!(vec & !0xf, 0);
+    }
+    assert_eq!(M::eye().rank(), 32);
+    assert!(M::eye().kernel().is_empty());
+}
+
+#[test]
+fn test_solve() {
+    use BitMatrix64 as M;
+    let matrix = (M::eye() ^ M::shl(3)) << 2;
+    let rhs = 0x1337133713371337 * matrix;
+    let space = matrix.solve(rhs).unwrap();
+    assert_eq!(space.dim(), 2);
+    let solutions: Vec<u64> = space.iter().collect();
+    assert_eq!(solutions.len(), 4);
+    assert!(solutions.contains(&0x1337133713371337));
+    for x in solutions {
+        assert_eq!(x * matrix, rhs);
+    }
+    // low two bits can never be set
+    assert!(matches!(
+        matrix.solve(rhs ^ 1),
+        Err(Error::NoSolution { equation: 0 })
+    ));
+    assert!(matches!(
+        matrix.solve(rhs ^ 0x8002),
+        Err(Error::NoSolution { equation: 1 })
+    ));
+}
+
+#[test]
+fn test_charpoly() {
+    let [m1, m2, m3] = crate::rand32::rng_matrix();
+    // each component is an LFSR on the high bits, the shifted out low bits
+    // only add powers of x
+    let lfsr1 =
+        Poly::from_words(&[(1 << 31) | (1 << 25) | (1 << 19) | (1 << 13) | 1]);
+    assert_eq!(m1.charpoly(), lfsr1.shl(1));
+    assert_eq!(m1.minpoly(), lfsr1.shl(1));
+    let lfsr2 = Poly::from_words(&[(1 << 29) | 0b101]);
+    assert_eq!(m2.charpoly(), lfsr2.shl(3));
+    assert_eq!(m2.minpoly(), lfsr2.shl(1));
+    assert_eq!(m3.charpoly().degree(), Some(32));
+    assert_eq!(m3.minpoly().degree(), Some(29));
+    assert_eq!(BitMatrix128::eye().minpoly(), Poly::from_words(&[0b11]));
+}
+
+#[test]
+fn test_eval_poly() {
+    let matrix = crate::xorshift128::XorShift128Rng::build_matrix();
+    let charpoly = matrix.charpoly();
+    assert_eq!(matrix.eval_poly(&charpoly), BitMatrix128::zero());
+    let steps = 0x1337_1337;
+    let jump = Poly::x().powmod(steps, &charpoly);
+    assert_eq!(matrix.eval_poly(&jump), matrix.pow(steps as usize));
+}
+
+#[test]
+fn test_from_fn() {
+    use crate::rand32_rev::{prev_s1, prev_s2, prev_s3};
+    use BitMatrix32 as M;
+    let back = crate::rand32::rng_back_matrix();
+    let prev = [prev_s1::<u32>, prev_s2, prev_s3];
+    for (matrix, prev) in back.into_iter().zip(prev) {
+        assert_eq!(M::check_linearity(prev, 64), Linearity::Linear);
+        assert_eq!(M::from_fn(prev), matrix);
+    }
+
+    let affine = |x: u32| (x >> 3) ^ 0x100000;
+    assert_eq!(
+        M::check_linearity(affine, 64),
+        Linearity::Affine { constant: 0x100000 }
+    );
+    assert_eq!(M::from_fn(affine), M::shr(3));
+    // only the low 3 bits of `x % 24` are the low bits of `x`
+    assert_eq!(
+        M::check_linearity(|x| x % 24, 64),
+        Linearity::Nonlinear { bits: 0b11000 }
+    );
+}
+
+#[test]
+fn test_xorshift_form() {
+    use BitMatrix32 as M;
+    for matrix in crate::rand32::rng_back_matrix() {
+        let terms = matrix.xorshift_form();
+        let min_terms = matrix.xorshift_form_min();
+        assert_eq!(M::from_xorshift_form(&terms), matrix);
+        assert_eq!(M::from_xorshift_form(&min_terms), matrix);
+        assert!(min_terms.len() <= terms.len());
+        let steps = matrix.xorshift_steps().unwrap();
+        assert_eq!(M::from_xorshift_steps(&steps), matrix);
+    }
+    // prev_s3 takes a chain of shifts, like the hand written loop
+    let prev_s3 = crate::rand32::rng_back_matrix()[2];
+    assert_eq!(prev_s3.xorshift_form_min().len(), 13);
+    assert_eq!(prev_s3.xorshift_steps().unwrap().len(), 5);
+
+    // x ^= x >> 3; x ^= x << 5 has a fill-in of every multiple of 3
+    let matrix = (M::eye() ^ M::shr(3)) * (M::eye() ^ M::shl(5));
+    let steps = matrix.xorshift_steps().unwrap();
+    assert!(steps.moves_identity());
+    assert_eq!(
+        steps.steps,
+        [(None, XorShiftOp::Shr(3)), (None, XorShiftOp::Shl(5))]
+    );
+    assert_eq!(M::from_xorshift_steps(&steps), matrix);
+    assert!(M::eye().and(0xff).xorshift_steps().is_some());
+}
+
+#[test]
+fn test_mul_many() {
+    use crate::xorshift128::XorShift128Rng;
+    let matrix = XorShift128Rng::build_matrix().pow(1000);
+    let mut rng = XorShift128Rng {
+        state0: 0x1337133713371337,
+        state1: 0xdeadbeefdeadbeef,
+    };
+    let vecs: Vec<u128> = (0..100)
+        .map(|_| {
+            let (s0, s1) = rng.next();
+            (s1 as u128) << 64 | s0 as u128
+        })
+        .collect();
+    let mut out = vec![0; vecs.len()];
+    matrix.mul_many(&vecs, &mut out);
+    for (&vec, &out) in vecs.iter().zip(out.iter()) {
+        assert_eq!(out, vec * matrix);
+    }
+
+    let [m1, _, _] = crate::rand32::rng_matrix();
+    let table = m1.table();
+    for vec in [0, 1, 0x80000000, 0xdeadbeef, u32::MAX] {
+        assert_eq!(vec * &table, vec * m1);
+    }
+}
+
+#[cfg(feature = "simd")]
+#[test]
+fn test_table_simd() {
+    let [m1, m2, _] = crate::rand32::rng_matrix();
+    let vecs: [u32; 4] = [1, 0x80000000, 0xdeadbeef, u32::MAX];
+    let table = m1.table();
+    let out = Simd::from_array(vecs) * &table;
+    assert_eq!(out.to_array(), vecs.map(|vec| vec * &table));
+    let table = BitMatrix64::from_fn(|vec| vec ^ (vec << 13)).table();
+    let wide = vecs.map(|vec| (vec as u64) << 29 | (vec * m2) as u64);
+    let out = Simd::from_array(wide) * &table;
+    assert_eq!(out.to_array(), wide.map(|vec| vec * &table));
+}
+
+#[cfg(feature = "simd")]
+#[test]
+fn test_mul_simd128() {
+    let matrix = crate::xorshift128::XorShift128Rng::build_matrix().pow(1000);
+    let vecs: [u128; 4] = [
+        1,
+        1 << 64,
+        0x0123456789abcdef_fedcba9876543210,
+        u128::MAX,
+    ];
+    let lo = Simd::from_array(vecs.map(|vec| vec as u64));
+    let hi = Simd::from_array(vecs.map(|vec| (vec >> 64) as u64));
+    let [lo, hi] = [lo, hi] * matrix;
+    for (ii, &vec) in vecs.iter().enumerate() {
+        assert_eq!(lo[ii] as u128 | (hi[ii] as u128) << 64, vec * matrix);
+    }
+}
+
+#[test]
+fn test_square_matrix() {
+    use crate::incremental::IncrementalSolver;
+
+    /// Recover `vec` from `vec * matrix` one output bit at a time
+    fn recover<M: SquareMatrix>(matrix: M, vec: M::Vector) -> M::Vector {
+        let out = matrix.mul_vec(vec);
+        let mut solver = IncrementalSolver::new(M::Vector::BITS);
+        for col in 0..M::Vector::BITS {
+            solver.add(matrix.column(col), out.get(col));
+        }
+        assert!(solver.is_determined());
+        solver.solution().unwrap().particular
+    }
+
+    let step = BitMatrix32::eye() ^ BitMatrix32::shl(5);
+    assert_eq!(recover(step, 0xdeadbeef), 0xdeadbeef);
+    let matrix = crate::xorshift128::XorShift128Rng::build_matrix();
+    let vec = 0x0123456789abcdef_fedcba9876543210;
+    assert_eq!(recover(matrix, vec), vec);
+}
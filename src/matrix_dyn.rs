@@ -0,0 +1,1008 @@
+use crate::matrix::{
+    AffineSpace, BitMatrix128, BitMatrix32, BitMatrix64, Error, XorShiftOp,
+};
+use crate::matrix_rect::{first_bit, words, BitMatrix};
+use crate::poly::Poly;
+use core::fmt;
+
+/// Heap allocated matrix with the size known only at runtime.
+///
+/// Rows are stored back to back as `u64` words, bit `jj` of row `ii` lives
+/// in `data[ii * stride + jj / 64]`. Like the fixed size matrices, vectors
+/// are multiplied from the left and row `ii` is the image of input bit `ii`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BitMatrixDyn {
+    rows: usize,
+    cols: usize,
+    stride: usize,
+    data: Vec<u64>,
+}
+
+impl fmt::Debug for BitMatrixDyn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        writeln!(f, "BitMatrixDyn<{}, {}>(", self.rows, self.cols)?;
+        for ii in 0..self.rows {
+            f.write_str("  ")?;
+            for jj in (0..self.cols).rev() {
+                f.write_str(if self.get(ii, jj) { "1" } else { "0" })?;
+            }
+            f.write_str("\n")?;
+        }
+        f.write_str(")")
+    }
+}
+
+fn get_bit(vec: &[u64], bit: usize) -> bool {
+    (vec[bit / 64] >> (bit % 64)) & 1 != 0
+}
+
+fn xor_into(dst: &mut [u64], src: &[u64]) {
+    for (dst, src) in dst.iter_mut().zip(src) {
+        *dst ^= src;
+    }
+}
+
+/// Mask of the valid bits in the last word of a `bits` long vector
+fn tail_mask(bits: usize) -> u64 {
+    match bits % 64 {
+        0 => !0,
+        rem => !0 >> (64 - rem),
+    }
+}
+
+fn shl_vec(vec: &[u64], bits: usize, len: usize) -> Vec<u64> {
+    let (words, rem) = (bits / 64, bits % 64);
+    let mut out = vec![0; vec.len()];
+    for ii in words..vec.len() {
+        out[ii] = vec[ii - words] << rem;
+        if rem != 0 && ii > words {
+            out[ii] |= vec[ii - words - 1] >> (64 - rem);
+        }
+    }
+    if let Some(last) = out.last_mut() {
+        *last &= tail_mask(len);
+    }
+    out
+}
+
+fn shr_vec(vec: &[u64], bits: usize) -> Vec<u64> {
+    let (words, rem) = (bits / 64, bits % 64);
+    let mut out = vec![0; vec.len()];
+    for ii in 0..vec.len().saturating_sub(words) {
+        out[ii] = vec[ii + words] >> rem;
+        if rem != 0 && ii + words + 1 < vec.len() {
+            out[ii] |= vec[ii + words + 1] << (64 - rem);
+        }
+    }
+    out
+}
+
+/// Vector of `len` bits with bits in `range` set
+fn range_mask(len: usize, range: core::ops::Range<usize>) -> Vec<u64> {
+    let mut out = vec![0; words(len)];
+    for bit in range {
+        out[bit / 64] |= 1 << (bit % 64);
+    }
+    out
+}
+
+impl BitMatrixDyn {
+    pub fn zero(rows: usize, cols: usize) -> Self {
+        let stride = words(cols);
+        Self {
+            rows,
+            cols,
+            stride,
+            data: vec![0; rows * stride],
+        }
+    }
+
+    pub fn eye(size: usize) -> Self {
+        let mut out = Self::zero(size, size);
+        for ii in 0..size {
+            out.set(ii, ii, true);
+        }
+        out
+    }
+
+    /// Build a matrix from rows, each row is `words(cols)` words long
+    pub fn from_rows<'a>(
+        cols: usize,
+        rows: impl IntoIterator<Item = &'a [u64]>,
+    ) -> Self {
+        let stride = words(cols);
+        let mut data = Vec::new();
+        let mut count = 0;
+        for row in rows {
+            assert_eq!(row.len(), stride, "row must be words(cols) long");
+            count += 1;
+            data.extend_from_slice(row);
+            if stride > 0 {
+                let last = data.len() - 1;
+                data[last] &= tail_mask(cols);
+            }
+        }
+        Self {
+            rows: count,
+            cols,
+            stride,
+            data,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn row(&self, row: usize) -> &[u64] {
+        &self.data[row * self.stride..(row + 1) * self.stride]
+    }
+
+    /// Callers must keep the bits past `cols` clear, the multiplication
+    /// relies on them
+    pub(crate) fn row_mut(&mut self, row: usize) -> &mut [u64] {
+        &mut self.data[row * self.stride..(row + 1) * self.stride]
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        assert!(row < self.rows && col < self.cols, "index out of bounds");
+        get_bit(self.row(row), col)
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: bool) {
+        assert!(row < self.rows && col < self.cols, "index out of bounds");
+        let mask = 1 << (col % 64);
+        let word = &mut self.row_mut(row)[col / 64];
+        if value {
+            *word |= mask;
+        } else {
+            *word &= !mask;
+        }
+    }
+
+    /// `self.row(dst) ^= self.row(src)`, skipping the first `from` words
+    fn xor_rows(&mut self, dst: usize, src: usize, from: usize) {
+        let stride = self.stride;
+        let (dst, src) = if dst < src {
+            let (head, tail) = self.data.split_at_mut(src * stride);
+            (&mut head[dst * stride..(dst + 1) * stride], &tail[..stride])
+        } else {
+            let (head, tail) = self.data.split_at_mut(dst * stride);
+            (&mut tail[..stride], &head[src * stride..(src + 1) * stride])
+        };
+        xor_into(&mut dst[from..], &src[from..]);
+    }
+
+    fn swap_rows(&mut self, aa: usize, bb: usize) {
+        if aa == bb {
+            return;
+        }
+        let stride = self.stride;
+        let (lo, hi) = (aa.min(bb), aa.max(bb));
+        let (head, tail) = self.data.split_at_mut(hi * stride);
+        head[lo * stride..(lo + 1) * stride]
+            .swap_with_slice(&mut tail[..stride]);
+    }
+
+    fn map_rows(&self, cols: usize, func: impl Fn(&[u64]) -> Vec<u64>) -> Self {
+        let rows: Vec<Vec<u64>> =
+            (0..self.rows).map(|ii| func(self.row(ii))).collect();
+        Self::from_rows(cols, rows.iter().map(|row| row.as_slice()))
+    }
+
+    pub fn pow(&self, mut pow: usize) -> Self {
+        assert_eq!(self.rows, self.cols, "matrix must be square");
+        let mut mul = self.clone();
+        let mut out = Self::eye(self.rows);
+        while pow > 0 {
+            if pow & 1 != 0 {
+                out = &out * &mul;
+            }
+            mul = &mul * &mul;
+            pow >>= 1;
+        }
+        out
+    }
+
+    pub fn and(&self, mask: &[u64]) -> Self {
+        self.map_rows(self.cols, |row| {
+            row.iter().zip(mask).map(|(row, mask)| row & mask).collect()
+        })
+    }
+
+    /// Matrix of `x >> bits` for a `size` bit vector
+    pub fn shr(size: usize, bits: u32) -> Self {
+        &Self::eye(size) >> bits
+    }
+
+    /// Matrix of `x << bits` for a `size` bit vector
+    pub fn shl(size: usize, bits: u32) -> Self {
+        &Self::eye(size) << bits
+    }
+
+    /// Place matrices along the diagonal, the rest is zero
+    pub fn block_diag(blocks: &[&Self]) -> Self {
+        let rows = blocks.iter().map(|block| block.rows).sum();
+        let cols = blocks.iter().map(|block| block.cols).sum();
+        let mut out = Self::zero(rows, cols);
+        let (mut row, mut col) = (0, 0);
+        for block in blocks {
+            out.paste(block, row, col);
+            row += block.rows;
+            col += block.cols;
+        }
+        out
+    }
+
+    /// Place matrices with the same number of rows side by side
+    pub fn hstack(blocks: &[&Self]) -> Self {
+        let rows = blocks.first().map_or(0, |block| block.rows);
+        assert!(
+            blocks.iter().all(|block| block.rows == rows),
+            "row counts must match"
+        );
+        let cols = blocks.iter().map(|block| block.cols).sum();
+        let mut out = Self::zero(rows, cols);
+        let mut col = 0;
+        for block in blocks {
+            out.paste(block, 0, col);
+            col += block.cols;
+        }
+        out
+    }
+
+    /// Place matrices with the same number of columns on top of each other
+    pub fn vstack(blocks: &[&Self]) -> Self {
+        let cols = blocks.first().map_or(0, |block| block.cols);
+        assert!(
+            blocks.iter().all(|block| block.cols == cols),
+            "column counts must match"
+        );
+        let rows = blocks.iter().map(|block| block.rows).sum();
+        let mut out = Self::zero(rows, cols);
+        let mut row = 0;
+        for block in blocks {
+            out.paste(block, row, 0);
+            row += block.rows;
+        }
+        out
+    }
+
+    /// Copy of the sub-matrix at the given row and column ranges
+    pub fn slice(
+        &self,
+        rows: core::ops::Range<usize>,
+        cols: core::ops::Range<usize>,
+    ) -> Self {
+        assert!(
+            rows.end <= self.rows && cols.end <= self.cols,
+            "slice out of bounds"
+        );
+        let width = cols.end - cols.start;
+        let mut out = Self::zero(rows.end - rows.start, width);
+        for (dst, src) in rows.enumerate() {
+            let mut row = shr_vec(self.row(src), cols.start);
+            row.truncate(out.stride);
+            if let Some(last) = row.last_mut() {
+                *last &= tail_mask(width);
+            }
+            out.row_mut(dst).copy_from_slice(&row);
+        }
+        out
+    }
+
+    fn swap_cols(&mut self, aa: usize, bb: usize) {
+        for ii in 0..self.rows {
+            let (bit_a, bit_b) = (self.get(ii, aa), self.get(ii, bb));
+            self.set(ii, aa, bit_b);
+            self.set(ii, bb, bit_a);
+        }
+    }
+
+    /// `col(dst) ^= col(src)`
+    fn xor_cols(&mut self, dst: usize, src: usize) {
+        for ii in 0..self.rows {
+            if self.get(ii, src) {
+                let row = self.row_mut(ii);
+                row[dst / 64] ^= 1 << (dst % 64);
+            }
+        }
+    }
+
+    /// Xor `block` into `self` with its top left corner at `(row, col)`
+    fn paste(&mut self, block: &Self, row: usize, col: usize) {
+        assert!(
+            row + block.rows <= self.rows && col + block.cols <= self.cols,
+            "block out of bounds"
+        );
+        let (word, rem) = (col / 64, col % 64);
+        for ii in 0..block.rows {
+            let dst = &mut self.row_mut(row + ii)[word..];
+            for (jj, &src) in block.row(ii).iter().enumerate() {
+                dst[jj] ^= src << rem;
+                if rem != 0 && src >> (64 - rem) != 0 {
+                    dst[jj + 1] ^= src >> (64 - rem);
+                }
+            }
+        }
+    }
+
+    /// Gauss-Jordan elimination, applying the same row operations to `comb`.
+    /// Returns the pivot column for each row up to the rank, the rows past
+    /// the rank are left zero.
+    ///
+    /// Uses the method of four russians: pivots are found for 8 columns at a
+    /// time, then every other row is cleared with a single lookup into a
+    /// table of pivot row combinations.
+    fn eliminate(&mut self, mut comb: Option<&mut Self>) -> Vec<usize> {
+        let mut pivots = Vec::new();
+        for start in (0..self.cols).step_by(8) {
+            let block_start = pivots.len();
+            for col in start..(start + 8).min(self.cols) {
+                let rank = pivots.len();
+                if rank == self.rows {
+                    break;
+                }
+                // find a row with this bit set, reducing the candidates by
+                // the pivots already in this block
+                let mut pos = None;
+                for jj in rank..self.rows {
+                    for (kk, &pivot) in
+                        pivots.iter().enumerate().skip(block_start)
+                    {
+                        if get_bit(self.row(jj), pivot) {
+                            self.xor_rows(jj, kk, 0);
+                            if let Some(comb) = comb.as_deref_mut() {
+                                comb.xor_rows(jj, kk, 0);
+                            }
+                        }
+                    }
+                    if get_bit(self.row(jj), col) {
+                        pos = Some(jj);
+                        break;
+                    }
+                }
+                let Some(pos) = pos else {
+                    continue;
+                };
+                self.swap_rows(rank, pos);
+                if let Some(comb) = comb.as_deref_mut() {
+                    comb.swap_rows(rank, pos);
+                }
+
+                // keep the block pivots reduced with each other
+                for kk in block_start..rank {
+                    if get_bit(self.row(kk), col) {
+                        self.xor_rows(kk, rank, 0);
+                        if let Some(comb) = comb.as_deref_mut() {
+                            comb.xor_rows(kk, rank, 0);
+                        }
+                    }
+                }
+                pivots.push(col);
+            }
+
+            // clear the block pivot columns from all other rows
+            let block = block_start..pivots.len();
+            if block.is_empty() {
+                continue;
+            }
+            // the block pivot rows are zero in every column before `start`,
+            // so the words before it stay unchanged
+            let from = start / 64;
+            let table = self.gray_table(block.clone());
+            let comb_table =
+                comb.as_deref().map(|comb| comb.gray_table(block.clone()));
+            for jj in 0..self.rows {
+                if block.contains(&jj) {
+                    continue;
+                }
+                let row = self.row(jj);
+                let idx = pivots[block.clone()].iter().enumerate().fold(
+                    0,
+                    |idx, (bit, &pivot)| {
+                        idx | (get_bit(row, pivot) as usize) << bit
+                    },
+                );
+                if idx == 0 {
+                    continue;
+                }
+                let stride = self.stride;
+                xor_into(
+                    &mut self.row_mut(jj)[from..],
+                    &table[idx * stride + from..(idx + 1) * stride],
+                );
+                if let (Some(comb), Some(comb_table)) =
+                    (comb.as_deref_mut(), comb_table.as_ref())
+                {
+                    let stride = comb.stride;
+                    xor_into(
+                        comb.row_mut(jj),
+                        &comb_table[idx * stride..(idx + 1) * stride],
+                    );
+                }
+            }
+        }
+        pivots
+    }
+
+    /// All xor combinations of the rows in `rows`, entry `ii` is stored at
+    /// `ii * stride` and is the xor of the rows selected by the bits of `ii`.
+    /// Filled in gray code order, so every entry costs a single xor.
+    fn gray_table(&self, rows: core::ops::Range<usize>) -> Vec<u64> {
+        let stride = self.stride;
+        let mut table = vec![0; stride << rows.len()];
+        let mut prev = 0;
+        for ii in 1_usize..1 << rows.len() {
+            let gray = ii ^ (ii >> 1);
+            let row = self.row(rows.start + ii.trailing_zeros() as usize);
+            let (head, tail) = table.split_at_mut(gray.max(prev) * stride);
+            let (dst, src) = if gray > prev {
+                (
+                    &mut tail[..stride],
+                    &head[prev * stride..(prev + 1) * stride],
+                )
+            } else {
+                (
+                    &mut head[gray * stride..(gray + 1) * stride],
+                    &tail[..stride],
+                )
+            };
+            dst.copy_from_slice(src);
+            xor_into(dst, row);
+            prev = gray;
+        }
+        table
+    }
+
+    pub fn inv(&self) -> Result<Self, Error> {
+        assert_eq!(self.rows, self.cols, "matrix must be square");
+        let mut tmp = self.clone();
+        let mut inv = Self::eye(self.rows);
+        let pivots = tmp.eliminate(Some(&mut inv));
+        if pivots.len() < self.rows {
+            let stuck_at_col = pivots
+                .iter()
+                .enumerate()
+                .find(|&(ii, &col)| ii != col)
+                .map_or(pivots.len(), |(ii, _)| ii);
+            return Err(Error::MatrixNotInvertible { stuck_at_col });
+        }
+        Ok(inv)
+    }
+
+    pub fn rank(&self) -> usize {
+        self.clone().eliminate(None).len()
+    }
+
+    /// Basis of all vectors `x` such that `x * self == 0`
+    pub fn kernel(&self) -> Vec<Vec<u64>> {
+        let mut tmp = self.clone();
+        let mut comb = Self::eye(self.rows);
+        let rank = tmp.eliminate(Some(&mut comb)).len();
+        (rank..self.rows).map(|ii| comb.row(ii).to_vec()).collect()
+    }
+
+    /// Find all vectors `x` such that `x * self == rhs`
+    pub fn solve(&self, rhs: &[u64]) -> Result<AffineSpace<Vec<u64>>, Error> {
+        assert_eq!(rhs.len(), self.stride, "rhs must be words(cols) long");
+        let mut tmp = self.clone();
+        let mut comb = Self::eye(self.rows);
+        let pivots = tmp.eliminate(Some(&mut comb));
+        let mut rest = rhs.to_vec();
+        let mut particular = vec![0; comb.stride];
+        for (ii, &col) in pivots.iter().enumerate() {
+            if get_bit(&rest, col) {
+                xor_into(&mut rest, tmp.row(ii));
+                xor_into(&mut particular, comb.row(ii));
+            }
+        }
+        if let Some(equation) = first_bit(&rest) {
+            return Err(Error::NoSolution { equation });
+        }
+        let rank = pivots.len();
+        Ok(AffineSpace {
+            particular,
+            kernel: (rank..self.rows).map(|ii| comb.row(ii).to_vec()).collect(),
+        })
+    }
+
+    /// Matrix `L` such that `self * L` is the identity, which exists when
+    /// the equations determine every unknown. For a consistent `rhs` the
+    /// solution of `x * self == rhs` is then `rhs * L`.
+    ///
+    /// Only the rows of the first independent equations are set, the later
+    /// equations are not checked, `solve` does that.
+    pub fn left_inverse(&self) -> Result<Self, Error> {
+        let mut tmp = self.clone();
+        let mut comb = Self::eye(self.rows);
+        let pivots = tmp.eliminate(Some(&mut comb));
+        if pivots.len() < self.rows {
+            return Err(Error::Underdetermined { rank: pivots.len() });
+        }
+        let mut out = Self::zero(self.cols, self.rows);
+        for (ii, &pivot) in pivots.iter().enumerate() {
+            out.row_mut(pivot).copy_from_slice(comb.row(ii));
+        }
+        Ok(out)
+    }
+
+    /// Characteristic polynomial, computed by reducing the matrix to the
+    /// upper Hessenberg form with similarity transforms
+    pub fn charpoly(&self) -> Poly {
+        assert_eq!(self.rows, self.cols, "matrix must be square");
+        let size = self.rows;
+        let mut hess = self.clone();
+        for col in 0..size.saturating_sub(2) {
+            let Some(pos) = (col + 1..size).find(|&ii| hess.get(ii, col))
+            else {
+                continue;
+            };
+            if pos != col + 1 {
+                hess.swap_rows(pos, col + 1);
+                hess.swap_cols(pos, col + 1);
+            }
+            for ii in col + 2..size {
+                if hess.get(ii, col) {
+                    // row_ii += row_pivot, then undo it with the columns
+                    hess.xor_rows(ii, col + 1, 0);
+                    hess.xor_cols(col + 1, ii);
+                }
+            }
+        }
+
+        // expand the determinant along the last column of each leading
+        // sub-matrix, polys[kk] is the charpoly of the top-left kk x kk
+        let mut polys = vec![Poly::one()];
+        for kk in 1..=size {
+            let mut poly = polys[kk - 1].shl(1);
+            if hess.get(kk - 1, kk - 1) {
+                poly = &poly + &polys[kk - 1];
+            }
+            for ii in (1..kk).rev() {
+                // product of the sub-diagonal from ii to kk - 1
+                if !hess.get(ii, ii - 1) {
+                    break;
+                }
+                if hess.get(ii - 1, kk - 1) {
+                    poly = &poly + &polys[ii - 1];
+                }
+            }
+            polys.push(poly);
+        }
+        polys.pop().unwrap()
+    }
+
+    /// Minimal polynomial, the lcm of the polynomials annihilating each of
+    /// the basis vectors
+    pub fn minpoly(&self) -> Poly {
+        assert_eq!(self.rows, self.cols, "matrix must be square");
+        let size = self.rows;
+        // span of all Krylov vectors seen so far, vectors inside of it are
+        // annihilated by the current result
+        let mut span = Vec::<(usize, Vec<u64>)>::new();
+        let mut result = Poly::one();
+        for ii in 0..size {
+            let mut vec = vec![0; self.stride];
+            vec[ii / 64] |= 1 << (ii % 64);
+            if reduce(&span, &mut vec).is_none() {
+                continue;
+            }
+
+            let (annihilator, basis) = self.krylov(vec);
+            result = result.lcm(&annihilator);
+
+            for mut row in basis {
+                if let Some(pivot) = reduce(&span, &mut row) {
+                    span.push((pivot, row));
+                }
+            }
+        }
+        result
+    }
+
+    /// Lowest degree polynomial `p` such that `vec * p(self) == 0`
+    pub fn annihilator(&self, vec: &[u64]) -> Poly {
+        assert_eq!(self.rows, self.cols, "matrix must be square");
+        assert_eq!(vec.len(), self.stride, "vector size must match");
+        self.krylov(vec.to_vec()).0
+    }
+
+    /// Reduce `v, vA, vA^2, ...` until one of them is dependent, returns
+    /// the annihilator of `v` and the reduced vectors
+    fn krylov(&self, vec: Vec<u64>) -> (Poly, Vec<Vec<u64>>) {
+        let mut basis = Vec::<(usize, Vec<u64>, Poly)>::new();
+        let mut power = vec;
+        let annihilator = loop {
+            let mut vec = power.clone();
+            let mut poly = Poly::monomial(basis.len());
+            for (pivot, row, row_poly) in basis.iter() {
+                if get_bit(&vec, *pivot) {
+                    xor_into(&mut vec, row);
+                    poly = &poly + row_poly;
+                }
+            }
+            let Some(pivot) = vec.iter().position(|&word| word != 0) else {
+                break poly;
+            };
+            let pivot = pivot * 64 + vec[pivot].trailing_zeros() as usize;
+            basis.push((pivot, vec, poly));
+            power = power.as_slice() * self;
+        };
+        let rows = basis.into_iter().map(|(_, row, _)| row).collect();
+        (annihilator, rows)
+    }
+
+    /// `poly(self)`, evaluated with Horner's method
+    pub fn eval_poly(&self, poly: &Poly) -> Self {
+        assert_eq!(self.rows, self.cols, "matrix must be square");
+        let mut out = Self::zero(self.rows, self.cols);
+        let Some(degree) = poly.degree() else {
+            return out;
+        };
+        let eye = Self::eye(self.rows);
+        for ii in (0..=degree).rev() {
+            out = &out * self;
+            if poly.coeff(ii) {
+                out = &out ^ &eye;
+            }
+        }
+        out
+    }
+
+    pub fn xorshift_form(&self) -> Vec<(Option<Vec<u64>>, XorShiftOp)> {
+        assert_eq!(self.rows, self.cols, "matrix must be square");
+        let size = self.rows;
+        let mut tmp = self.clone();
+        let mut result = Vec::new();
+        for ii in 0..size {
+            for jj in 0..size {
+                if !tmp.get(ii, jj) {
+                    continue;
+                }
+                let len = size - ii.max(jj);
+                let mut last = 0;
+                for kk in 0..len {
+                    if !tmp.get(ii + kk, jj + kk) {
+                        break;
+                    }
+                    tmp.set(ii + kk, jj + kk, false);
+                    last = kk + 1;
+                }
+                use core::cmp::Ordering::*;
+                use XorShiftOp::*;
+                let (op, op_range) = match ii.cmp(&jj) {
+                    Equal => (Ident, 0..size),
+                    Less => (Shl((jj - ii) as u32), 0..size - (jj - ii)),
+                    Greater => (Shr((ii - jj) as u32), ii - jj..size),
+                };
+                let range = ii..ii + last;
+                let mask = if range == op_range {
+                    None
+                } else {
+                    Some(range_mask(size, range))
+                };
+                result.push((mask, op));
+            }
+        }
+        result
+    }
+}
+
+/// Reduce `vec` by the rows of an echelon basis, returning the pivot of
+/// what is left or `None` if the vector was in the span
+fn reduce(basis: &[(usize, Vec<u64>)], vec: &mut [u64]) -> Option<usize> {
+    for (pivot, row) in basis {
+        if get_bit(vec, *pivot) {
+            xor_into(vec, row);
+        }
+    }
+    let word = vec.iter().position(|&word| word != 0)?;
+    Some(word * 64 + vec[word].trailing_zeros() as usize)
+}
+
+impl core::ops::BitXor for &BitMatrixDyn {
+    type Output = BitMatrixDyn;
+    fn bitxor(self, other: Self) -> Self::Output {
+        assert!(
+            self.rows == other.rows && self.cols == other.cols,
+            "matrix sizes must match"
+        );
+        let mut out = self.clone();
+        xor_into(&mut out.data, &other.data);
+        out
+    }
+}
+
+impl core::ops::Mul for &BitMatrixDyn {
+    type Output = BitMatrixDyn;
+    /// Method of four russians: each byte of a row selects one of 256
+    /// precomputed combinations of the corresponding 8 rows
+    fn mul(self, other: Self) -> Self::Output {
+        assert_eq!(self.cols, other.rows, "matrix sizes must match");
+        let mut out = BitMatrixDyn::zero(self.rows, other.cols);
+        let stride = out.stride;
+        for start in (0..other.rows).step_by(8) {
+            let table = other.gray_table(start..(start + 8).min(other.rows));
+            for ii in 0..self.rows {
+                let idx = (self.row(ii)[start / 64] >> (start % 64)) & 0xff;
+                let idx = idx as usize;
+                xor_into(
+                    out.row_mut(ii),
+                    &table[idx * stride..(idx + 1) * stride],
+                );
+            }
+        }
+        out
+    }
+}
+
+impl core::ops::Mul<&BitMatrixDyn> for &[u64] {
+    type Output = Vec<u64>;
+    fn mul(self, matrix: &BitMatrixDyn) -> Self::Output {
+        assert_eq!(self.len(), words(matrix.rows), "vector size must match");
+        let mut out = vec![0; matrix.stride];
+        for ii in 0..matrix.rows {
+            if get_bit(self, ii) {
+                xor_into(&mut out, matrix.row(ii));
+            }
+        }
+        out
+    }
+}
+
+impl core::ops::Shr<u32> for &BitMatrixDyn {
+    type Output = BitMatrixDyn;
+    fn shr(self, bits: u32) -> Self::Output {
+        self.map_rows(self.cols, |row| shr_vec(row, bits as usize))
+    }
+}
+
+impl core::ops::Shl<u32> for &BitMatrixDyn {
+    type Output = BitMatrixDyn;
+    fn shl(self, bits: u32) -> Self::Output {
+        self.map_rows(self.cols, |row| shl_vec(row, bits as usize, self.cols))
+    }
+}
+
+macro_rules! impl_from_square {
+    ($name:ident, $ty:ty) => {
+        impl From<$name> for BitMatrixDyn {
+            fn from(matrix: $name) -> Self {
+                let size = matrix.0.len();
+                let mut out = Self::zero(size, size);
+                for (ii, row) in matrix.0.into_iter().enumerate() {
+                    for (jj, word) in out.row_mut(ii).iter_mut().enumerate() {
+                        *word = (row >> (jj * 64)) as u64;
+                    }
+                }
+                out
+            }
+        }
+
+        impl TryFrom<&BitMatrixDyn> for $name {
+            type Error = Error;
+            fn try_from(matrix: &BitMatrixDyn) -> Result<Self, Error> {
+                let size = $name::zero().0.len();
+                if matrix.rows != size || matrix.cols != size {
+                    return Err(Error::SizeMismatch {
+                        rows: matrix.rows,
+                        cols: matrix.cols,
+                    });
+                }
+                let mut out = $name::zero();
+                for (ii, row) in out.0.iter_mut().enumerate() {
+                    for (jj, &word) in matrix.row(ii).iter().enumerate() {
+                        *row |= (word as $ty) << (jj * 64);
+                    }
+                }
+                Ok(out)
+            }
+        }
+    };
+}
+
+impl_from_square!(BitMatrix32, u32);
+impl_from_square!(BitMatrix64, u64);
+impl_from_square!(BitMatrix128, u128);
+
+impl<const R: usize, const C: usize, const W: usize> From<BitMatrix<R, C, W>>
+    for BitMatrixDyn
+{
+    fn from(matrix: BitMatrix<R, C, W>) -> Self {
+        let mut out = Self::zero(R, C);
+        for (ii, row) in matrix.0.iter().enumerate() {
+            out.row_mut(ii).copy_from_slice(row);
+        }
+        out
+    }
+}
+
+impl<const R: usize, const C: usize, const W: usize> TryFrom<&BitMatrixDyn>
+    for BitMatrix<R, C, W>
+{
+    type Error = Error;
+    fn try_from(matrix: &BitMatrixDyn) -> Result<Self, Error> {
+        if matrix.rows != R || matrix.cols != C {
+            return Err(Error::SizeMismatch {
+                rows: matrix.rows,
+                cols: matrix.cols,
+            });
+        }
+        let mut out = Self::zero();
+        for (ii, row) in out.0.iter_mut().enumerate() {
+            row.copy_from_slice(matrix.row(ii));
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BitMatrixDyn;
+    use crate::matrix::{BitMatrix128, BitMatrix32, Error};
+    use crate::poly::Poly;
+    use crate::splitmix::splitmix64;
+    use crate::xorshift128::XorShift128Rng;
+
+    fn random_matrix(size: usize, rng: &mut u64) -> BitMatrixDyn {
+        let mut out = BitMatrixDyn::zero(size, size);
+        for ii in 0..size {
+            for word in out.row_mut(ii) {
+                *word = splitmix64(rng);
+            }
+        }
+        let last = out.stride - 1;
+        for ii in 0..size {
+            out.row_mut(ii)[last] &= super::tail_mask(size);
+        }
+        out
+    }
+
+    #[test]
+    fn test_matches_fixed() {
+        let fixed = XorShift128Rng::build_matrix();
+        let matrix = BitMatrixDyn::from(fixed);
+        assert_eq!(BitMatrixDyn::from(fixed.pow(1337)), matrix.pow(1337));
+        assert_eq!(
+            BitMatrixDyn::from(fixed.inv().unwrap()),
+            matrix.inv().unwrap()
+        );
+        let shifted =
+            BitMatrix128::eye() ^ BitMatrix128::shr(67) ^ BitMatrix128::shl(3);
+        let dyn_shifted = &(&BitMatrixDyn::eye(128)
+            ^ &BitMatrixDyn::shr(128, 67))
+            ^ &BitMatrixDyn::shl(128, 3);
+        assert_eq!(BitMatrixDyn::from(shifted), dyn_shifted);
+        assert_eq!(
+            format!("{:?}", shifted.xorshift_form()),
+            format!("{:?}", dyn_shifted.xorshift_form())
+                .replace("Some([", "Some(")
+                .replace("])", ")")
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        let shl = BitMatrixDyn::shl(40, 3);
+        let eye = BitMatrixDyn::eye(30);
+        let diag = BitMatrixDyn::block_diag(&[&shl, &eye, &shl]);
+        assert_eq!((diag.rows(), diag.cols()), (110, 110));
+        assert_eq!(diag.slice(0..40, 0..40), shl);
+        assert_eq!(diag.slice(40..70, 40..70), eye);
+        assert_eq!(diag.slice(70..110, 70..110), shl);
+        assert_eq!(diag.slice(0..40, 40..110), BitMatrixDyn::zero(40, 70));
+
+        let wide =
+            BitMatrixDyn::hstack(&[&shl, &shl, &diag.slice(0..40, 0..7)]);
+        assert_eq!((wide.rows(), wide.cols()), (40, 87));
+        assert_eq!(wide.slice(0..40, 40..80), shl);
+        let tall = BitMatrixDyn::vstack(&[&eye, &diag.slice(70..110, 80..110)]);
+        assert_eq!(tall.slice(30..70, 0..30), diag.slice(70..110, 80..110));
+        assert_eq!(
+            BitMatrix32::try_from(&BitMatrixDyn::eye(32)).unwrap(),
+            BitMatrix32::eye()
+        );
+        assert!(BitMatrix32::try_from(&eye).is_err());
+
+        let empty: [&[u64]; 3] = [&[], &[], &[]];
+        let empty = BitMatrixDyn::from_rows(0, empty);
+        assert_eq!(empty, BitMatrixDyn::zero(3, 0));
+        let rows: [&[u64]; 1] = [&[!0]];
+        assert_eq!(BitMatrixDyn::from_rows(5, rows).row(0), [0x1f]);
+    }
+
+    #[test]
+    fn test_inv_solve() {
+        let mut rng = 0x1337133713371337;
+        let size = 300;
+        let matrix = loop {
+            let matrix = random_matrix(size, &mut rng);
+            if matrix.rank() == size {
+                break matrix;
+            }
+        };
+        let inv = matrix.inv().unwrap();
+        assert_eq!(&matrix * &inv, BitMatrixDyn::eye(size));
+
+        // make the last row dependent on the first two
+        let mut singular = matrix.clone();
+        let dependent: Vec<u64> = singular
+            .row(0)
+            .iter()
+            .zip(singular.row(1))
+            .map(|(a, b)| a ^ b)
+            .collect();
+        singular.row_mut(size - 1).copy_from_slice(&dependent);
+        assert_eq!(singular.rank(), size - 1);
+        assert!(singular.inv().is_err());
+        let kernel = singular.kernel();
+        assert_eq!(kernel.len(), 1);
+        assert!((kernel[0].as_slice() * &singular).iter().all(|&w| w == 0));
+
+        let x: Vec<u64> =
+            (0..inv.stride).map(|_| splitmix64(&mut rng)).collect();
+        let mut x = x;
+        *x.last_mut().unwrap() &= super::tail_mask(size);
+        let rhs = x.as_slice() * &singular;
+        let space = singular.solve(&rhs).unwrap();
+        assert_eq!(space.particular.as_slice() * &singular, rhs);
+
+        // the same unknowns observed twice
+        let twice = BitMatrixDyn::hstack(&[&matrix, &matrix]);
+        let left = twice.left_inverse().unwrap();
+        assert_eq!(&twice * &left, BitMatrixDyn::eye(size));
+        assert!(matches!(
+            singular.left_inverse(),
+            Err(Error::Underdetermined { rank }) if rank == size - 1
+        ));
+        let mut rhs = x.as_slice() * &twice;
+        rhs[(size + 5) / 64] ^= 1 << ((size + 5) % 64);
+        assert!(matches!(
+            twice.solve(&rhs),
+            Err(Error::NoSolution { equation }) if equation == size + 5
+        ));
+    }
+
+    #[test]
+    fn test_charpoly_minpoly() {
+        // companion matrix of x^5 + x^2 + 1, shifting up with feedback
+        let target = Poly::from_words(&[0b100101]);
+        let mut companion = BitMatrixDyn::shr(5, 1);
+        for ii in [0, 2] {
+            companion.set(ii, 4, true);
+        }
+        assert_eq!(companion.charpoly(), target);
+        assert_eq!(companion.minpoly(), target);
+
+        let eye = BitMatrixDyn::eye(70);
+        let x_plus_one = Poly::from_words(&[0b11]);
+        let mut power = Poly::one();
+        for _ in 0..70 {
+            power = &power * &x_plus_one;
+        }
+        assert_eq!(eye.charpoly(), power);
+        assert_eq!(eye.minpoly(), x_plus_one);
+
+        let mut rng = 0xdeadbeefcafebabe;
+        let matrix = random_matrix(150, &mut rng);
+        let charpoly = matrix.charpoly();
+        let minpoly = matrix.minpoly();
+        assert_eq!(charpoly.degree(), Some(150));
+        assert!((&charpoly % &minpoly).is_zero());
+        // Cayley-Hamilton
+        let mut sum = BitMatrixDyn::zero(150, 150);
+        let mut power = BitMatrixDyn::eye(150);
+        for ii in 0..=150 {
+            if minpoly.coeff(ii) {
+                sum = &sum ^ &power;
+            }
+            power = &power * &matrix;
+        }
+        assert_eq!(sum, BitMatrixDyn::zero(150, 150));
+    }
+}
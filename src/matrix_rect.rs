@@ -0,0 +1,374 @@
+use crate::matrix::{
+    AffineSpace, BitMatrix128, BitMatrix32, BitMatrix64, Error,
+};
+use core::fmt;
+
+/// Number of `u64` words needed to store `bits` bits
+pub const fn words(bits: usize) -> usize {
+    bits.div_ceil(64)
+}
+
+/// Rectangular matrix with `R` rows and `C` columns, each row is stored in
+/// `W` words, where `W` must be equal to `words(C)`.
+///
+/// Like the square matrices, vectors are multiplied from the left: a vector
+/// of `R` bits times the matrix produces a vector of `C` bits, so rows are
+/// the unknowns and columns are the equations.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct BitMatrix<const R: usize, const C: usize, const W: usize>(
+    pub [[u64; W]; R],
+);
+
+impl<const R: usize, const C: usize, const W: usize> fmt::Debug
+    for BitMatrix<R, C, W>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        writeln!(f, "BitMatrix<{}, {}>(", R, C)?;
+        for row in self.0.iter() {
+            f.write_str("  ")?;
+            for col in (0..C).rev() {
+                let bit = (row[col / 64] >> (col % 64)) & 1;
+                f.write_str(if bit != 0 { "1" } else { "0" })?;
+            }
+            f.write_str("\n")?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// Index of the lowest set bit of a vector stored as words
+pub(crate) fn first_bit(vec: &[u64]) -> Option<usize> {
+    let word = vec.iter().position(|&word| word != 0)?;
+    Some(word * 64 + vec[word].trailing_zeros() as usize)
+}
+
+fn get_bit<const W: usize>(vec: &[u64; W], bit: usize) -> bool {
+    (vec[bit / 64] >> (bit % 64)) & 1 != 0
+}
+
+fn xor_into<const W: usize>(dst: &mut [u64; W], src: &[u64; W]) {
+    for (dst, src) in dst.iter_mut().zip(src) {
+        *dst ^= src;
+    }
+}
+
+impl<const R: usize, const C: usize, const W: usize> BitMatrix<R, C, W> {
+    const WORDS_MATCH: () =
+        assert!(W == words(C), "W must be equal to words(C)");
+
+    pub fn zero() -> Self {
+        let () = Self::WORDS_MATCH;
+        Self([[0; W]; R])
+    }
+
+    /// Ones on the main diagonal, the rest of a non-square matrix is zero
+    pub fn eye() -> Self {
+        let mut out = Self::zero();
+        for ii in 0..R.min(C) {
+            out.set(ii, ii, true);
+        }
+        out
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        assert!(row < R && col < C, "index out of bounds");
+        get_bit(&self.0[row], col)
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: bool) {
+        assert!(row < R && col < C, "index out of bounds");
+        let mask = 1 << (col % 64);
+        if value {
+            self.0[row][col / 64] |= mask;
+        } else {
+            self.0[row][col / 64] &= !mask;
+        }
+    }
+
+    pub fn transpose<const W2: usize>(&self) -> BitMatrix<C, R, W2> {
+        let mut out = BitMatrix::<C, R, W2>::zero();
+        for (ii, row) in self.0.iter().enumerate() {
+            for jj in 0..C {
+                if get_bit(row, jj) {
+                    out.0[jj][ii / 64] |= 1 << (ii % 64);
+                }
+            }
+        }
+        out
+    }
+
+    /// Reduced row echelon form of the matrix, rows past the rank are zero
+    /// and their combinations span the kernel
+    fn echelon<const WR: usize>(&self) -> Echelon<R, W, WR> {
+        assert!(WR == words(R), "WR must be equal to words(R)");
+        let mut rows = self.0;
+        let mut comb = [[0; WR]; R];
+        for (ii, row) in comb.iter_mut().enumerate() {
+            row[ii / 64] = 1 << (ii % 64);
+        }
+        let mut pivots = [0; R];
+        let mut rank = 0;
+        for col in 0..C {
+            if rank == R {
+                break;
+            }
+            let Some(pos) = (rank..R).find(|&jj| get_bit(&rows[jj], col))
+            else {
+                continue;
+            };
+            rows.swap(rank, pos);
+            comb.swap(rank, pos);
+            let (pivot_row, pivot_comb) = (rows[rank], comb[rank]);
+            for jj in 0..R {
+                if jj != rank && get_bit(&rows[jj], col) {
+                    xor_into(&mut rows[jj], &pivot_row);
+                    xor_into(&mut comb[jj], &pivot_comb);
+                }
+            }
+            pivots[rank] = col;
+            rank += 1;
+        }
+        Echelon {
+            rows,
+            comb,
+            pivots,
+            rank,
+        }
+    }
+
+    pub fn rank(&self) -> usize {
+        let mut rows = self.0;
+        let mut rank = 0;
+        for col in 0..C {
+            let Some(pos) = (rank..R).find(|&jj| get_bit(&rows[jj], col))
+            else {
+                continue;
+            };
+            rows.swap(rank, pos);
+            let pivot_row = rows[rank];
+            for row in rows[rank + 1..].iter_mut() {
+                if get_bit(row, col) {
+                    xor_into(row, &pivot_row);
+                }
+            }
+            rank += 1;
+        }
+        rank
+    }
+
+    /// Basis of all vectors `x` such that `x * self == 0`, `WR` must be
+    /// equal to `words(R)`
+    pub fn kernel<const WR: usize>(&self) -> Vec<[u64; WR]> {
+        let ech = self.echelon::<WR>();
+        ech.comb[ech.rank..].to_vec()
+    }
+
+    /// Find all vectors `x` such that `x * self == rhs`, `WR` must be equal
+    /// to `words(R)`
+    pub fn solve<const WR: usize>(
+        &self,
+        rhs: [u64; W],
+    ) -> Result<AffineSpace<[u64; WR]>, Error> {
+        let ech = self.echelon::<WR>();
+        let mut rest = rhs;
+        let mut particular = [0; WR];
+        for ii in 0..ech.rank {
+            if get_bit(&rest, ech.pivots[ii]) {
+                xor_into(&mut rest, &ech.rows[ii]);
+                xor_into(&mut particular, &ech.comb[ii]);
+            }
+        }
+        if let Some(equation) = first_bit(&rest) {
+            return Err(Error::NoSolution { equation });
+        }
+        Ok(AffineSpace {
+            particular,
+            kernel: ech.comb[ech.rank..].to_vec(),
+        })
+    }
+
+    /// Matrix `L` such that `self * L` is the identity, which exists when
+    /// the equations determine every unknown. For a consistent `rhs` the
+    /// solution of `x * self == rhs` is then `rhs * L`, it reads only the
+    /// first independent equations, see `solve` for checking the others.
+    /// `WR` must be equal to `words(R)`.
+    pub fn left_inverse<const WR: usize>(
+        &self,
+    ) -> Result<BitMatrix<C, R, WR>, Error> {
+        let ech = self.echelon::<WR>();
+        if ech.rank < R {
+            return Err(Error::Underdetermined { rank: ech.rank });
+        }
+        let mut out = BitMatrix::zero();
+        for (&pivot, comb) in ech.pivots.iter().zip(ech.comb.iter()) {
+            out.0[pivot] = *comb;
+        }
+        Ok(out)
+    }
+}
+
+/// Reduced row echelon form along with the combinations of the original rows
+/// that produced each reduced row
+struct Echelon<const R: usize, const W: usize, const WR: usize> {
+    rows: [[u64; W]; R],
+    comb: [[u64; WR]; R],
+    pivots: [usize; R],
+    rank: usize,
+}
+
+impl<const R: usize, const C: usize, const W: usize> core::ops::BitXor
+    for BitMatrix<R, C, W>
+{
+    type Output = Self;
+    fn bitxor(mut self, other: Self) -> Self::Output {
+        for (dst, src) in self.0.iter_mut().zip(other.0.iter()) {
+            xor_into(dst, src);
+        }
+        self
+    }
+}
+
+impl<
+        const R: usize,
+        const K: usize,
+        const C: usize,
+        const W1: usize,
+        const W2: usize,
+    > core::ops::Mul<BitMatrix<K, C, W2>> for BitMatrix<R, K, W1>
+{
+    type Output = BitMatrix<R, C, W2>;
+    fn mul(self, other: BitMatrix<K, C, W2>) -> Self::Output {
+        let mut out = BitMatrix::zero();
+        for (dst, row) in out.0.iter_mut().zip(self.0) {
+            *dst = row * other;
+        }
+        out
+    }
+}
+
+impl<const R: usize, const C: usize, const W: usize, const WR: usize>
+    core::ops::Mul<BitMatrix<R, C, W>> for [u64; WR]
+{
+    type Output = [u64; W];
+    fn mul(self, matrix: BitMatrix<R, C, W>) -> Self::Output {
+        assert!(WR == words(R), "vector size must match the row count");
+        let mut out = [0; W];
+        for (ii, row) in matrix.0.iter().enumerate() {
+            if get_bit(&self, ii) {
+                xor_into(&mut out, row);
+            }
+        }
+        out
+    }
+}
+
+macro_rules! impl_from_square {
+    ($name:ident, $ty:ty, $bits:expr) => {
+        impl From<$name> for BitMatrix<$bits, $bits, { words($bits) }> {
+            fn from(matrix: $name) -> Self {
+                let mut out = Self::zero();
+                for (dst, row) in out.0.iter_mut().zip(matrix.0) {
+                    for (ii, word) in dst.iter_mut().enumerate() {
+                        *word = (row >> (ii * 64)) as u64;
+                    }
+                }
+                out
+            }
+        }
+
+        impl From<BitMatrix<$bits, $bits, { words($bits) }>> for $name {
+            fn from(matrix: BitMatrix<$bits, $bits, { words($bits) }>) -> Self {
+                let arr = core::array::from_fn(|ii| {
+                    let mut row: $ty = 0;
+                    for (jj, &word) in matrix.0[ii].iter().enumerate() {
+                        row |= (word as $ty) << (jj * 64);
+                    }
+                    row
+                });
+                $name(arr)
+            }
+        }
+    };
+}
+
+impl_from_square!(BitMatrix32, u32, 32);
+impl_from_square!(BitMatrix64, u64, 64);
+impl_from_square!(BitMatrix128, u128, 128);
+
+#[cfg(test)]
+mod tests {
+    use super::{words, BitMatrix};
+    use crate::matrix::{BitMatrix128, BitMatrix32, Error};
+    use crate::rand32::{rng_matrix, Rand32Ref};
+
+    #[test]
+    fn test_square_roundtrip() {
+        let matrix = BitMatrix32::eye() ^ BitMatrix32::shl(7);
+        let rect: BitMatrix<32, 32, 1> = matrix.into();
+        assert_eq!(BitMatrix32::from(rect), matrix);
+        assert_eq!([0x1337] * rect, [(0x1337 * matrix) as u64]);
+
+        let matrix = BitMatrix128::eye() ^ BitMatrix128::shr(65);
+        let rect: BitMatrix<128, 128, 2> = matrix.into();
+        let square = matrix * matrix;
+        assert_eq!(BitMatrix128::from(rect * rect), square);
+        let transposed: BitMatrix<128, 128, 2> = rect.transpose();
+        assert_eq!(transposed.transpose::<2>(), rect);
+        assert!(transposed.get(0, 65) && !transposed.get(65, 0));
+    }
+
+    #[test]
+    fn test_mod24_system() {
+        // 88 unknown seed bits, 3 known bits out of each of 40 outputs
+        const VALUES: usize = 40;
+        const UNKNOWNS: usize = 88;
+        const EQUATIONS: usize = VALUES * 3;
+        let fields = [(0, 1), (31, 3), (60, 4)];
+        let mut system =
+            BitMatrix::<UNKNOWNS, EQUATIONS, { words(EQUATIONS) }>::zero();
+        for (matrix, (offset, skip)) in rng_matrix().into_iter().zip(fields) {
+            let mut step = matrix;
+            for value in 0..VALUES {
+                for bit in skip..32 {
+                    let out = (1 << bit) * step;
+                    for eq in 0..3 {
+                        let eq_bit = (out >> eq) & 1 != 0;
+                        system.set(offset + bit - skip, value * 3 + eq, eq_bit);
+                    }
+                }
+                step = step * matrix;
+            }
+        }
+        assert_eq!(system.rank(), UNKNOWNS);
+
+        let mut rng = Rand32Ref::seeded(0x13371337, 0xdeadbeef, 0xcafebabe);
+        let (s1, s2, s3) = rng.state();
+        let mut rhs = [0; words(EQUATIONS)];
+        for value in 0..VALUES {
+            let bits = (rng.rand() % 24) as u64 & 0b111;
+            let pos = value * 3;
+            rhs[pos / 64] |= bits << (pos % 64);
+            if pos % 64 > 61 {
+                rhs[pos / 64 + 1] |= bits >> (64 - pos % 64);
+            }
+        }
+        let space = system.solve::<{ words(UNKNOWNS) }>(rhs).unwrap();
+        assert!(space.kernel.is_empty());
+        let unknowns = (s1 >> 1) as u128
+            | ((s2 >> 3) as u128) << 31
+            | ((s3 >> 4) as u128) << 60;
+        let expected = [unknowns as u64, (unknowns >> 64) as u64];
+        assert_eq!(space.particular, expected);
+
+        let left = system.left_inverse::<{ words(UNKNOWNS) }>().unwrap();
+        assert_eq!(system * left, BitMatrix::eye());
+        assert_eq!(rhs * left, expected);
+        // the first 88 equations are independent, so any later one that is
+        // wrong is the first conflict
+        rhs[100 / 64] ^= 1 << (100 % 64);
+        assert!(matches!(
+            system.solve::<{ words(UNKNOWNS) }>(rhs),
+            Err(Error::NoSolution { equation: 100 })
+        ));
+    }
+}
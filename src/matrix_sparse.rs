@@ -0,0 +1,529 @@
+use crate::matrix::{AffineSpace, Error};
+use crate::matrix_dyn::BitMatrixDyn;
+use crate::matrix_rect::words;
+use core::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Equations lighter than this are eliminated sparsely, once every
+/// remaining equation is heavier the rest is solved densely
+const MAX_SPARSE_WEIGHT: usize = 64;
+
+/// Matrix stored as the sorted list of set columns of each row.
+///
+/// Same convention as the dense matrices, row `ii` is the image of input
+/// bit `ii` and the columns of `x * self == rhs` are the equations. Meant
+/// for the transition and observation matrices of generators with large
+/// states, where a row only has a handful of bits set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitMatrixSparse {
+    cols: usize,
+    data: Vec<Vec<usize>>,
+}
+
+fn get_bit(vec: &[u64], bit: usize) -> bool {
+    (vec[bit / 64] >> (bit % 64)) & 1 != 0
+}
+
+/// Symmetric difference of two sorted lists
+fn xor_sorted(a: &[usize], b: &[usize]) -> Vec<usize> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut ii, mut jj) = (0, 0);
+    while ii < a.len() && jj < b.len() {
+        match a[ii].cmp(&b[jj]) {
+            core::cmp::Ordering::Less => {
+                out.push(a[ii]);
+                ii += 1;
+            }
+            core::cmp::Ordering::Greater => {
+                out.push(b[jj]);
+                jj += 1;
+            }
+            core::cmp::Ordering::Equal => {
+                ii += 1;
+                jj += 1;
+            }
+        }
+    }
+    out.extend_from_slice(&a[ii..]);
+    out.extend_from_slice(&b[jj..]);
+    out
+}
+
+/// Sort the columns, a column listed twice cancels out
+fn normalize(mut row: Vec<usize>) -> Vec<usize> {
+    row.sort_unstable();
+    let mut out: Vec<usize> = Vec::with_capacity(row.len());
+    for col in row {
+        if out.last() == Some(&col) {
+            out.pop();
+        } else {
+            out.push(col);
+        }
+    }
+    out
+}
+
+/// Equation `XOR of the vars == rhs`
+#[derive(Clone, Debug)]
+struct Equation {
+    vars: Vec<usize>,
+    rhs: bool,
+}
+
+/// Outcome of the sparse phase: the equations that were used to eliminate
+/// a pivot variable, in order, and the equations that are left over with
+/// their original index. The left over equations do not contain any of the
+/// pivots. `conflict` is the first equation that reduced to `0 == 1`.
+struct Reduced {
+    pivots: Vec<(usize, Equation)>,
+    rest: Vec<(usize, Equation)>,
+    conflict: Option<usize>,
+}
+
+/// The lowest active equation that contains `var`
+fn first_occurrence(occurs: &[Vec<usize>], var: usize) -> Option<usize> {
+    occurs[var].iter().copied().min()
+}
+
+/// Markowitz style elimination: take the lightest equation and pivot on
+/// its variable that occurs in the fewest other equations, which keeps the
+/// fill-in low.
+///
+/// With `in_order`, an equation only pivots on a variable no earlier
+/// active equation contains, so it is only ever added into the equations
+/// after it. Every equation stays its original plus a combination of
+/// earlier ones, and the first equation to reduce to `0 == 1` is the first
+/// one that contradicts the equations before it, same as with the column
+/// order of the dense solvers. This leaves more for the dense phase.
+fn reduce(vars: usize, equations: Vec<Equation>, in_order: bool) -> Reduced {
+    let mut occurs: Vec<Vec<usize>> = vec![Vec::new(); vars];
+    for (index, eq) in equations.iter().enumerate() {
+        for &var in eq.vars.iter() {
+            occurs[var].push(index);
+        }
+    }
+    let mut active = vec![true; equations.len()];
+    let mut heap: BinaryHeap<_> = equations
+        .iter()
+        .enumerate()
+        .map(|(index, eq)| Reverse((eq.vars.len(), index)))
+        .collect();
+    let mut equations = equations;
+    let mut pivots = Vec::new();
+    let mut conflict: Option<usize> = None;
+
+    // the heap holds stale weights, an entry counts only while it matches
+    // the current weight of an active equation
+    while let Some(Reverse((weight, index))) = heap.pop() {
+        if !active[index] || equations[index].vars.len() != weight {
+            continue;
+        }
+        if weight == 0 {
+            if equations[index].rhs {
+                conflict = Some(conflict.map_or(index, |c| c.min(index)));
+            }
+            active[index] = false;
+            continue;
+        }
+        if weight > MAX_SPARSE_WEIGHT {
+            break;
+        }
+        // an equation without such a variable waits for the earlier ones,
+        // it is pushed again once one of them is pivoted
+        let Some(pivot) = equations[index]
+            .vars
+            .iter()
+            .copied()
+            .filter(|&var| {
+                !in_order || first_occurrence(&occurs, var) == Some(index)
+            })
+            .min_by_key(|&var| occurs[var].len())
+        else {
+            continue;
+        };
+        active[index] = false;
+        let eq = equations[index].clone();
+        for &var in eq.vars.iter() {
+            occurs[var].retain(|&other| other != index);
+        }
+        for other in core::mem::take(&mut occurs[pivot]) {
+            // the variables of `eq` toggle in `other`
+            for &var in eq.vars.iter().filter(|&&var| var != pivot) {
+                match occurs[var].iter().position(|&ii| ii == other) {
+                    Some(pos) => {
+                        occurs[var].swap_remove(pos);
+                    }
+                    None => occurs[var].push(other),
+                }
+            }
+            equations[other].vars =
+                xor_sorted(&equations[other].vars, &eq.vars);
+            equations[other].rhs ^= eq.rhs;
+            heap.push(Reverse((equations[other].vars.len(), other)));
+        }
+        // the variables of `eq` left it and some of the other equations,
+        // they may have a new first equation now
+        for &var in eq.vars.iter() {
+            if let Some(first) = first_occurrence(&occurs, var) {
+                let weight = equations[first].vars.len();
+                heap.push(Reverse((weight, first)));
+            }
+        }
+        pivots.push((pivot, eq));
+    }
+
+    let rest = equations
+        .into_iter()
+        .zip(active)
+        .enumerate()
+        .filter(|(_, (eq, active))| *active && !eq.vars.is_empty())
+        .map(|(index, (eq, _))| (index, eq))
+        .collect();
+    Reduced {
+        pivots,
+        rest,
+        conflict,
+    }
+}
+
+impl BitMatrixSparse {
+    pub fn zero(rows: usize, cols: usize) -> Self {
+        Self {
+            cols,
+            data: vec![Vec::new(); rows],
+        }
+    }
+
+    pub fn eye(size: usize) -> Self {
+        Self {
+            cols: size,
+            data: (0..size).map(|ii| vec![ii]).collect(),
+        }
+    }
+
+    /// Build a matrix from the set columns of each row, in any order. A
+    /// column listed twice cancels out.
+    pub fn from_rows(
+        cols: usize,
+        rows: impl IntoIterator<Item = Vec<usize>>,
+    ) -> Self {
+        let data: Vec<Vec<usize>> = rows.into_iter().map(normalize).collect();
+        assert!(
+            data.iter().flatten().all(|&col| col < cols),
+            "column out of range"
+        );
+        Self { cols, data }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Sorted set columns of `row`
+    pub fn row(&self, row: usize) -> &[usize] {
+        &self.data[row]
+    }
+
+    /// Number of set bits
+    pub fn nnz(&self) -> usize {
+        self.data.iter().map(Vec::len).sum()
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> bool {
+        assert!(col < self.cols, "index out of bounds");
+        self.data[row].binary_search(&col).is_ok()
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: bool) {
+        assert!(col < self.cols, "index out of bounds");
+        let row = &mut self.data[row];
+        match (row.binary_search(&col), value) {
+            (Err(pos), true) => row.insert(pos, col),
+            (Ok(pos), false) => {
+                row.remove(pos);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut data = vec![Vec::new(); self.cols];
+        for (ii, row) in self.data.iter().enumerate() {
+            for &col in row.iter() {
+                data[col].push(ii);
+            }
+        }
+        Self {
+            cols: self.rows(),
+            data,
+        }
+    }
+
+    pub fn to_dense(&self) -> BitMatrixDyn {
+        let mut out = BitMatrixDyn::zero(self.rows(), self.cols);
+        for (ii, row) in self.data.iter().enumerate() {
+            for &col in row.iter() {
+                out.set(ii, col, true);
+            }
+        }
+        out
+    }
+
+    /// Find all vectors `x` such that `x * self == rhs`.
+    ///
+    /// Light equations are eliminated sparsely first, what is left once the
+    /// fill-in makes every equation heavy goes through `BitMatrixDyn`, so
+    /// the dense part only spans the unknowns the sparse phase could not
+    /// pivot on. The error reports the first conflicting equation like the
+    /// dense solvers. Only eliminating in the order of the equations tells
+    /// which one that is, so once the system turns out to be inconsistent it
+    /// is reduced again that way.
+    pub fn solve(&self, rhs: &[u64]) -> Result<AffineSpace<Vec<u64>>, Error> {
+        assert_eq!(rhs.len(), words(self.cols), "rhs must be words(cols) long");
+        let equations: Vec<Equation> = self
+            .transpose()
+            .data
+            .into_iter()
+            .enumerate()
+            .map(|(col, vars)| Equation {
+                vars,
+                rhs: get_bit(rhs, col),
+            })
+            .collect();
+        match self.solve_equations(equations.clone(), false) {
+            Err(Error::NoSolution { .. }) => {
+                self.solve_equations(equations, true)
+            }
+            result => result,
+        }
+    }
+
+    fn solve_equations(
+        &self,
+        equations: Vec<Equation>,
+        in_order: bool,
+    ) -> Result<AffineSpace<Vec<u64>>, Error> {
+        let vars = self.rows();
+        let Reduced {
+            pivots,
+            rest,
+            conflict,
+        } = reduce(vars, equations, in_order);
+
+        // every unknown that is not a pivot goes into the dense system,
+        // the ones no equation mentions end up in its kernel
+        let mut is_pivot = vec![false; vars];
+        for &(pivot, _) in pivots.iter() {
+            is_pivot[pivot] = true;
+        }
+        let free: Vec<usize> =
+            (0..vars).filter(|&var| !is_pivot[var]).collect();
+        let mut index = vec![usize::MAX; vars];
+        for (ii, &var) in free.iter().enumerate() {
+            index[var] = ii;
+        }
+        let mut dense = BitMatrixDyn::zero(free.len(), rest.len());
+        let mut dense_rhs = vec![0; words(rest.len())];
+        for (jj, (_, eq)) in rest.iter().enumerate() {
+            for &var in eq.vars.iter() {
+                dense.set(index[var], jj, true);
+            }
+            dense_rhs[jj / 64] |= (eq.rhs as u64) << (jj % 64);
+        }
+        // the left over equations are in their original order, so the
+        // dense solver finds the first conflict among them
+        let space = dense.solve(&dense_rhs);
+        let dense_conflict = match space {
+            Err(Error::NoSolution { equation }) => Some(rest[equation].0),
+            _ => None,
+        };
+        if let Some(equation) = conflict.into_iter().chain(dense_conflict).min()
+        {
+            return Err(Error::NoSolution { equation });
+        }
+        let space = space?;
+
+        // each pivot was eliminated from the equations after it, while the
+        // earlier pivot equations can still contain it, so walking back
+        // from the last pivot every other variable of an equation is known
+        let lift = |compact: &[u64], with_rhs: bool| {
+            let mut vec = vec![0; words(vars)];
+            for (ii, &var) in free.iter().enumerate() {
+                if get_bit(compact, ii) {
+                    vec[var / 64] |= 1 << (var % 64);
+                }
+            }
+            for (pivot, eq) in pivots.iter().rev() {
+                let mut bit = eq.rhs && with_rhs;
+                for &var in eq.vars.iter().filter(|&var| var != pivot) {
+                    bit ^= get_bit(&vec, var);
+                }
+                vec[pivot / 64] |= (bit as u64) << (pivot % 64);
+            }
+            vec
+        };
+        Ok(AffineSpace {
+            particular: lift(&space.particular, true),
+            kernel: space.kernel.iter().map(|vec| lift(vec, false)).collect(),
+        })
+    }
+}
+
+impl From<&BitMatrixDyn> for BitMatrixSparse {
+    fn from(matrix: &BitMatrixDyn) -> Self {
+        Self {
+            cols: matrix.cols(),
+            data: (0..matrix.rows())
+                .map(|ii| {
+                    (0..matrix.cols())
+                        .filter(|&col| matrix.get(ii, col))
+                        .collect()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl core::ops::Mul for &BitMatrixSparse {
+    type Output = BitMatrixSparse;
+    fn mul(self, other: &BitMatrixSparse) -> Self::Output {
+        assert_eq!(self.cols, other.rows(), "matrix sizes must match");
+        let mut set = vec![false; other.cols];
+        let data = self
+            .data
+            .iter()
+            .map(|row| {
+                let mut touched = Vec::new();
+                for &ii in row.iter() {
+                    for &col in other.data[ii].iter() {
+                        set[col] = !set[col];
+                        touched.push(col);
+                    }
+                }
+                touched.sort_unstable();
+                touched.dedup();
+                touched.retain(|&col| core::mem::take(&mut set[col]));
+                touched
+            })
+            .collect();
+        BitMatrixSparse {
+            cols: other.cols,
+            data,
+        }
+    }
+}
+
+impl core::ops::Mul<&BitMatrixSparse> for &[u64] {
+    type Output = Vec<u64>;
+    fn mul(self, matrix: &BitMatrixSparse) -> Self::Output {
+        assert_eq!(self.len(), words(matrix.rows()), "vector size must match");
+        let mut out = vec![0; words(matrix.cols)];
+        for (ii, row) in matrix.data.iter().enumerate() {
+            if get_bit(self, ii) {
+                for &col in row.iter() {
+                    out[col / 64] ^= 1 << (col % 64);
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BitMatrixSparse;
+    use crate::matrix::Error;
+    use crate::matrix_rect::words;
+    use crate::splitmix::splitmix64;
+
+    #[test]
+    fn test_small() {
+        let matrix = BitMatrixSparse::from_rows(
+            4,
+            [vec![0, 1], vec![1, 2, 2], vec![3, 0], vec![]],
+        );
+        assert_eq!(matrix.row(1), [1]);
+        assert_eq!(matrix.nnz(), 5);
+        let dense = matrix.to_dense();
+        assert_eq!(BitMatrixSparse::from(&dense), matrix);
+        let square = &matrix * &matrix.transpose();
+        assert_eq!(square.to_dense(), &dense * &matrix.transpose().to_dense());
+
+        let rhs = [0b1011];
+        let space = matrix.solve(&rhs).unwrap();
+        assert_eq!(space.particular, [0b0110]);
+        assert_eq!(space.kernel, [vec![0b1000]]);
+        // column 2 is zero, setting its bit contradicts
+        assert!(matches!(
+            matrix.solve(&[0b1111]),
+            Err(Error::NoSolution { equation: 2 })
+        ));
+    }
+
+    /// Every unknown feeds three equations far apart, like the observations
+    /// of a generator with a large state
+    #[test]
+    fn test_large_system() {
+        let vars = 2000;
+        let mut state = 0x1337;
+        let matrix = BitMatrixSparse::from_rows(
+            vars + 100,
+            (0..vars).map(|ii| {
+                let mut row = vec![ii, (ii * 7 + 3) % vars];
+                row.push(splitmix64(&mut state) as usize % (vars + 100));
+                row
+            }),
+        );
+        let mut vector: Vec<u64> =
+            (0..words(vars)).map(|_| splitmix64(&mut state)).collect();
+        vector[vars / 64] &= (1 << (vars % 64)) - 1;
+        let rhs = &vector[..] * &matrix;
+
+        let space = matrix.solve(&rhs).unwrap();
+        assert_eq!(&space.particular[..] * &matrix, rhs);
+        for vec in space.kernel.iter() {
+            assert!((&vec[..] * &matrix).iter().all(|&word| word == 0));
+        }
+        let dense = matrix.to_dense();
+        assert_eq!(space.kernel.len(), vars - dense.rank());
+
+        // the first conflict matches the dense solver, wherever the
+        // changed bit ends up in the elimination
+        for bit in [17, 900, vars + 66] {
+            let mut bad = rhs.clone();
+            bad[bit / 64] ^= 1 << (bit % 64);
+            let Err(Error::NoSolution { equation }) = dense.solve(&bad) else {
+                panic!("changed rhs must be inconsistent");
+            };
+            assert!(matches!(
+                matrix.solve(&bad),
+                Err(Error::NoSolution { equation: found }) if found == equation
+            ));
+        }
+    }
+
+    /// Random equations are too heavy for the sparse phase
+    #[test]
+    fn test_dense_fallback() {
+        let (vars, cols) = (150, 160);
+        let mut state = 0xdead;
+        let matrix = BitMatrixSparse::from_rows(
+            cols,
+            (0..vars).map(|_| {
+                (0..cols)
+                    .filter(|_| splitmix64(&mut state) & 1 != 0)
+                    .collect()
+            }),
+        );
+        let vector: Vec<u64> =
+            (0..words(vars)).map(|ii| 0x1337 << ii).collect();
+        let rhs = &vector[..] * &matrix;
+        let space = matrix.solve(&rhs).unwrap();
+        assert_eq!(&space.particular[..] * &matrix, rhs);
+        assert_eq!(space.kernel.len(), vars - matrix.to_dense().rank());
+    }
+}
@@ -1,44 +1,11 @@
-use crate::matrix::{
-    BitMatrix128 as M128, BitMatrix32 as M32, Error as MatError,
-};
-use crate::rand32::{rng_matrix, Rand32Ref};
-
-const MAX_INDEX: usize = 30;
-fn build_inv_matrix() -> Result<M128, MatError> {
-    let [m1, m2, m3] = rng_matrix();
-    let pows: [[M32; 3]; MAX_INDEX] = core::array::from_fn(|ii| {
-        let pow = ii + 1;
-        [m1.pow(pow), m2.pow(pow), m3.pow(pow)]
-    });
-
-    let mut arr = [0_u128; 128];
-    for mat in 0..3 {
-        for row in 0..32 {
-            let mut row_val = 0;
-            for (ii, pow) in pows.iter().enumerate() {
-                let val = pow[mat].0[row] as u128;
-                let val = (val & 0b111) << (ii * 3);
-                row_val |= val;
-            }
+use crate::layout::VariableLayout;
+use crate::matrix::AffineSpace;
+use crate::matrix_rect::words;
+use crate::rand32::{layout_to_state, rng_layout, Rand32Ref};
+use std::sync::OnceLock;
 
-            let o_row = mat * 32 + row;
-            arr[o_row] = row_val;
-        }
-    }
-    let mut mat = M128(arr);
-
-    // skip under-constrained input bits
-    mat = mat.vskip(64..68).vskip(32..35).vskip(0..1);
-    // drop extra bits
-    mat = mat.hskip(88..128);
-
-    // fill the rest with ones;
-    for ii in 88..128 {
-        mat.0[ii] = 1 << ii;
-    }
-
-    mat.inv()
-}
+/// Values covered by the low bits equations built up front
+const OBSERVED: usize = 64;
 
 fn produce_mod24(
     rng: &mut Rand32Ref,
@@ -47,79 +14,229 @@ fn produce_mod24(
     (0..count).map(|_| rng.rand() % 24)
 }
 
-fn mod24_to_vector(mod24: &[u32]) -> u128 {
-    assert!(mod24.len() > 20, "must provide at least 20 values");
-    let mut vec = 0_u128;
-    for (ii, m24) in mod24[..MAX_INDEX.min(mod24.len())].iter().enumerate() {
-        let value = (m24 & 0b111) as u128;
-        vec |= value << (ii * 3);
-    }
-    let mask = 128 - 88;
-    (vec << mask) >> mask
+/// Low bits of the values in terms of the unknowns of `rng_layout`, one
+/// equation per bit, brought into echelon form once so that each sequence
+/// only takes a few masked parities.
+///
+/// Every row and check is the sum of the equations up to its last one, so
+/// the plan of a prefix of the equations is a prefix of the rows and the
+/// checks. The check with the lowest last equation that fails gives the
+/// shortest prefix without a solution.
+#[derive(Clone)]
+struct LowBitsPlan {
+    /// Generator with only unknown `ii` set, past the values covered so
+    /// far. The fixed bits of the layout are zero, so its outputs are the
+    /// coefficients of `ii`.
+    states: Vec<(u32, u32, u32)>,
+    equations: usize,
+    rows: Vec<PlanRow>,
+    /// Sums of equations whose coefficients cancel, by last equation
+    checks: Vec<(usize, Vec<u64>)>,
 }
 
-fn vector_to_seed(mut vector: u128) -> (u32, u32, u32) {
-    let max_u32 = 0xffff_ffff;
-    let s1 = ((vector << 1) & max_u32) as u32;
-    vector >>= 32 - 1;
-    let s2 = ((vector << 3) & max_u32) as u32;
-    vector >>= 32 - 3;
-    let s3 = ((vector << 4) & max_u32) as u32;
-    (s1, s2, s3)
+/// Independent equation, none of the later rows contains its pivot
+#[derive(Clone)]
+struct PlanRow {
+    last: usize,
+    pivot: usize,
+    coeffs: u128,
+    sum: Vec<u64>,
+}
+
+fn parity(vec: &[u64], mask: &[u64]) -> bool {
+    let ones: u32 = vec
+        .iter()
+        .zip(mask)
+        .map(|(a, b)| (a & b).count_ones())
+        .sum();
+    ones & 1 != 0
+}
+
+impl LowBitsPlan {
+    fn new(layout: &VariableLayout) -> Self {
+        Self {
+            states: (0..layout.len())
+                .map(|ii| layout_to_state(layout, 1 << ii))
+                .collect(),
+            equations: 0,
+            rows: Vec::new(),
+            checks: Vec::new(),
+        }
+    }
+
+    /// Plan of the first `OBSERVED` values, built on first use
+    fn shared() -> &'static Self {
+        static PLAN: OnceLock<LowBitsPlan> = OnceLock::new();
+        PLAN.get_or_init(|| {
+            let mut plan = Self::new(&rng_layout());
+            plan.extend(OBSERVED);
+            plan
+        })
+    }
+
+    /// Number of values covered
+    fn values(&self) -> usize {
+        self.equations / 3
+    }
+
+    /// Cover the first `values` values
+    fn extend(&mut self, values: usize) {
+        for _ in self.values()..values {
+            let mut low_bits = [0; 3];
+            for (ii, state) in self.states.iter_mut().enumerate() {
+                let mut rng = Rand32Ref::from_state(*state);
+                let value = rng.rand() % 24;
+                *state = rng.state();
+                for (bit, coeffs) in low_bits.iter_mut().enumerate() {
+                    *coeffs |= (((value >> bit) & 1) as u128) << ii;
+                }
+            }
+            for coeffs in low_bits {
+                self.add(coeffs);
+            }
+        }
+    }
+
+    fn add(&mut self, mut coeffs: u128) {
+        let last = self.equations;
+        self.equations += 1;
+        let mut sum = vec![0; words(self.equations)];
+        sum[last / 64] |= 1 << (last % 64);
+        // a row has none of the earlier pivots, so clearing them in order
+        // never sets one that is already cleared
+        for row in self.rows.iter() {
+            if (coeffs >> row.pivot) & 1 != 0 {
+                coeffs ^= row.coeffs;
+                for (dst, src) in sum.iter_mut().zip(row.sum.iter()) {
+                    *dst ^= src;
+                }
+            }
+        }
+        if coeffs == 0 {
+            self.checks.push((last, sum));
+        } else {
+            let pivot = coeffs.trailing_zeros() as usize;
+            self.rows.push(PlanRow {
+                last,
+                pivot,
+                coeffs,
+                sum,
+            });
+        }
+    }
+
+    /// Every vector of unknowns that agrees with the low bits of
+    /// `sequence`, or the first value whose low bits contradict the ones
+    /// before it
+    fn solve(&self, sequence: &[u32]) -> Result<AffineSpace<u128>, Error> {
+        let equations = 3 * sequence.len();
+        assert!(equations <= self.equations, "sequence is too long");
+        let mut rhs = vec![0; words(equations)];
+        for (jj, value) in sequence.iter().enumerate() {
+            rhs[3 * jj / 64] |= ((value & 0b111) as u64) << (3 * jj % 64);
+            if 3 * jj % 64 > 61 {
+                rhs[3 * jj / 64 + 1] |=
+                    ((value & 0b111) as u64) >> (64 - 3 * jj % 64);
+            }
+        }
+        let failed = self
+            .checks
+            .iter()
+            .take_while(|(last, _)| *last < equations)
+            .find(|(_, sum)| parity(&rhs, sum));
+        if let Some(&(last, _)) = failed {
+            return Err(Error::Inconsistent { index: last / 3 });
+        }
+
+        // back substitution, a row only has its own pivot and later ones
+        // besides the free unknowns
+        let rows = self.rows.iter().take_while(|row| row.last < equations);
+        let rows: Vec<&PlanRow> = rows.collect();
+        let substitute = |mut vec: u128, rhs: &[u64]| {
+            for row in rows.iter().rev() {
+                let ones = (row.coeffs & vec).count_ones() & 1 != 0;
+                if ones != parity(rhs, &row.sum) {
+                    vec |= 1 << row.pivot;
+                }
+            }
+            vec
+        };
+        let mut pivots = 0_u128;
+        for row in rows.iter() {
+            pivots |= 1 << row.pivot;
+        }
+        let kernel = (0..self.states.len())
+            .filter(|&ii| (pivots >> ii) & 1 == 0)
+            .map(|free| substitute(1 << free, &[]))
+            .collect();
+        Ok(AffineSpace {
+            particular: substitute(0, &rhs),
+            kernel,
+        })
+    }
 }
 
 pub struct Mod24Solver {
-    inv_matrix: M128,
+    layout: VariableLayout,
 }
 
 #[derive(Debug)]
 pub enum Error {
     SequenceTooShort,
+    /// The low bits of the values up to `index` have no common solution,
+    /// while the ones before it do. One of them is wrong, since the first
+    /// 30 values are needed to pin down the state, a wrong value among them
+    /// is only caught at index 29 or later.
+    Inconsistent {
+        index: usize,
+    },
+    /// The low bits pin down the state, which does not produce the values
     ValidationFailed,
+    /// None of the states the low bits allow produce the values
     NotFound,
 }
 
 impl Mod24Solver {
     pub fn new() -> Self {
-        let inv_matrix = build_inv_matrix()
-            .expect("could not build inverse matrix, blame Desu");
+        Self {
+            layout: rng_layout(),
+        }
+    }
 
-        Self { inv_matrix }
+    /// All the low bits of the sequence, one equation each
+    fn solve_low_bits(
+        &self,
+        sequence: &[u32],
+    ) -> Result<AffineSpace<u128>, Error> {
+        let plan = LowBitsPlan::shared();
+        if sequence.len() <= plan.values() {
+            plan.solve(sequence)
+        } else {
+            let mut plan = plan.clone();
+            plan.extend(sequence.len());
+            plan.solve(sequence)
+        }
     }
 
     pub fn solve(&self, sequence: &[u32]) -> Result<(u32, u32, u32), Error> {
-        let known_values = sequence.len();
-        if known_values < 20 {
+        if sequence.len() < 20 {
             return Err(Error::SequenceTooShort);
         }
-        let known_bits = known_values * 3;
-        let unknown_bits = 88_usize.saturating_sub(known_bits);
-        if unknown_bits == 0 {
-            let vector = mod24_to_vector(sequence);
-            let out_vector = vector * self.inv_matrix;
-            let state = vector_to_seed(out_vector);
+        // the low bits of every value go into one system, the states it
+        // allows are checked against the whole values
+        let space = self.solve_low_bits(sequence)?;
+        for vector in space.iter() {
+            let state = layout_to_state(&self.layout, vector);
             let mut rng = Rand32Ref::from_state(state);
 
-            let generated = produce_mod24(&mut rng, known_values);
+            let generated = produce_mod24(&mut rng, sequence.len());
             if sequence.iter().copied().eq(generated) {
-                Ok(state)
-            } else {
-                Err(Error::ValidationFailed)
+                return Ok(state);
             }
+        }
+        if space.dim() == 0 {
+            Err(Error::ValidationFailed)
         } else {
-            let vector = mod24_to_vector(sequence);
-            for brute in 0..(1 << unknown_bits) {
-                let vector = vector | (brute << known_bits);
-                let out_vector = vector * self.inv_matrix;
-                let state = vector_to_seed(out_vector);
-                let mut rng = Rand32Ref::from_state(state);
-
-                let generated = produce_mod24(&mut rng, known_values);
-                if sequence.iter().copied().eq(generated) {
-                    return Ok(state);
-                }
-            }
-
             Err(Error::NotFound)
         }
     }
@@ -133,25 +250,69 @@ impl Default for Mod24Solver {
 
 #[cfg(test)]
 mod tests {
-    use super::{produce_mod24, Mod24Solver, Rand32Ref};
+    use super::{
+        produce_mod24, Error, LowBitsPlan, Mod24Solver, Rand32Ref, OBSERVED,
+    };
+    use crate::rand32::{
+        rng_layout, rng_outputs_matrix, rng_seed_map, RNG_UNKNOWNS,
+        RNG_UNKNOWN_BITS,
+    };
+
+    #[test]
+    fn test_plan() {
+        let mut plan = LowBitsPlan::shared().clone();
+        plan.extend(OBSERVED + 6);
+        // the low 3 bits of each output in terms of the 96-bit state
+        let positions = rng_layout().state_positions();
+        let outputs = rng_outputs_matrix(plan.values());
+        let sum_coeffs = |sum: &[u64]| {
+            let mut coeffs = 0;
+            for eq in 0..plan.equations {
+                if (sum.get(eq / 64).unwrap_or(&0) >> (eq % 64)) & 1 == 0 {
+                    continue;
+                }
+                for (ii, &pos) in positions.iter().enumerate() {
+                    if outputs.get(pos, eq / 3 * 32 + eq % 3) {
+                        coeffs ^= 1 << ii;
+                    }
+                }
+            }
+            coeffs
+        };
+        for row in plan.rows.iter() {
+            assert_eq!(sum_coeffs(&row.sum), row.coeffs);
+        }
+        for (_, sum) in plan.checks.iter() {
+            assert_eq!(sum_coeffs(sum), 0);
+        }
+        // the first 30 values pin down every unknown
+        assert_eq!(plan.rows.len(), RNG_UNKNOWNS);
+        assert!(plan.rows.iter().all(|row| row.last < 90));
+    }
+
+    /// The recovered state must match the seeded one on every bit that
+    /// reaches the outputs
+    fn assert_seeded(state: (u32, u32, u32), seed: u32) {
+        let expected = rng_seed_map().map(|map| map.apply(seed));
+        let found = [state.0, state.1, state.2];
+        for ii in 0..3 {
+            let unknown = RNG_UNKNOWN_BITS[ii];
+            assert_eq!(found[ii] & unknown, expected[ii] & unknown);
+        }
+    }
 
     #[test]
     fn test_example() {
         let mod24_solver = Mod24Solver::new();
-        let s1_mask = !0x100001;
-        let s2_mask = !0x1007;
-        let s3_mask = !0x1f;
         let mut mod24s = Vec::new();
         for seed in 0..0xffff {
             let seed = (seed << 8) ^ 0x13371337;
             let mut rng = Rand32Ref::seeded(seed, seed, seed);
             mod24s.clear();
             mod24s.extend(produce_mod24(&mut rng, 40));
-            let (s1, s2, s3) =
+            let state =
                 mod24_solver.solve(&mod24s).expect("should find a solution");
-            assert_eq!(s1 & s1_mask, seed & s1_mask);
-            assert_eq!(s2 & s2_mask, seed & s2_mask);
-            assert_eq!(s3 & s3_mask, seed & s3_mask);
+            assert_seeded(state, seed);
         }
     }
 
@@ -159,19 +320,58 @@ mod tests {
     fn test_brute() {
         let mod24_solver = Mod24Solver::new();
         let seed = 0x13371337;
-        let s1_mask = !0x100001;
-        let s2_mask = !0x1007;
-        let s3_mask = !0x1f;
         let mut mod24s = Vec::new();
         for len in 22..30 {
             let mut rng = Rand32Ref::seeded(seed, seed, seed);
             mod24s.clear();
             mod24s.extend(produce_mod24(&mut rng, len));
-            let (s1, s2, s3) =
+            // every low bit removes one candidate dimension
+            let space = LowBitsPlan::shared().solve(&mod24s).unwrap();
+            assert_eq!(space.dim(), RNG_UNKNOWNS - 3 * len);
+            let state =
                 mod24_solver.solve(&mod24s).expect("should find a solution");
-            assert_eq!(s1 & s1_mask, seed & s1_mask);
-            assert_eq!(s2 & s2_mask, seed & s2_mask);
-            assert_eq!(s3 & s3_mask, seed & s3_mask);
+            assert_seeded(state, seed);
         }
     }
+
+    #[test]
+    fn test_inconsistent() {
+        let mod24_solver = Mod24Solver::new();
+        let mut rng = Rand32Ref::seeded(0x13371337, 0xdeadbeef, 0xcafebabe);
+        let mod24s: Vec<u32> = produce_mod24(&mut rng, 40).collect();
+
+        let mut broken = mod24s.clone();
+        broken[33] ^= 0b100;
+        assert!(matches!(
+            mod24_solver.solve(&broken),
+            Err(Error::Inconsistent { index: 33 })
+        ));
+        // a wrong value among the first 30 is caught once later values
+        // contradict the state it implies
+        let mut broken = mod24s.clone();
+        broken[5] ^= 0b001;
+        let Err(Error::Inconsistent { index }) = mod24_solver.solve(&broken)
+        else {
+            panic!("value 5 is wrong");
+        };
+        assert!((29..40).contains(&index));
+        // the mod 3 part is not linear and only fails the validation
+        let mut broken = mod24s.clone();
+        broken[33] = (broken[33] + 8) % 24;
+        assert!(matches!(
+            mod24_solver.solve(&broken),
+            Err(Error::ValidationFailed)
+        ));
+        assert!(mod24_solver.solve(&mod24s).is_ok());
+
+        // past the values covered up front
+        let mut rng = Rand32Ref::seeded(0x13371337, 0xdeadbeef, 0xcafebabe);
+        let mut long: Vec<u32> = produce_mod24(&mut rng, 70).collect();
+        assert!(mod24_solver.solve(&long).is_ok());
+        long[68] ^= 0b010;
+        assert!(matches!(
+            mod24_solver.solve(&long),
+            Err(Error::Inconsistent { index: 68 })
+        ));
+    }
 }
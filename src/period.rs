@@ -0,0 +1,354 @@
+use crate::matrix_dyn::BitMatrixDyn;
+use crate::poly::Poly;
+
+fn add_mod(aa: u128, bb: u128, modulus: u128) -> u128 {
+    let (sum, overflow) = aa.overflowing_add(bb);
+    if overflow || sum >= modulus {
+        sum.wrapping_sub(modulus)
+    } else {
+        sum
+    }
+}
+
+fn mul_mod(aa: u128, bb: u128, modulus: u128) -> u128 {
+    if modulus <= u64::MAX as u128 {
+        return (aa % modulus) * (bb % modulus) % modulus;
+    }
+    // no 256 bit product, double and add
+    let (mut aa, mut bb, mut out) = (aa % modulus, bb, 0);
+    while bb > 0 {
+        if bb & 1 != 0 {
+            out = add_mod(out, aa, modulus);
+        }
+        aa = add_mod(aa, aa, modulus);
+        bb >>= 1;
+    }
+    out
+}
+
+fn pow_mod(base: u128, mut exp: u128, modulus: u128) -> u128 {
+    let mut base = base % modulus;
+    let mut out = 1 % modulus;
+    while exp > 0 {
+        if exp & 1 != 0 {
+            out = mul_mod(out, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exp >>= 1;
+    }
+    out
+}
+
+fn gcd(mut aa: u128, mut bb: u128) -> u128 {
+    while bb != 0 {
+        (aa, bb) = (bb, aa % bb);
+    }
+    aa
+}
+
+fn lcm(aa: u128, bb: u128) -> u128 {
+    (aa / gcd(aa, bb))
+        .checked_mul(bb)
+        .expect("lcm overflows u128")
+}
+
+const SMALL_PRIMES: [u128; 16] =
+    [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+/// Miller-Rabin with the first 16 primes as bases, which is deterministic
+/// well past 64 bits and probabilistic beyond that
+pub fn is_prime(num: u128) -> bool {
+    if num < 2 {
+        return false;
+    }
+    for prime in SMALL_PRIMES {
+        if num.is_multiple_of(prime) {
+            return num == prime;
+        }
+    }
+    let zeros = (num - 1).trailing_zeros();
+    let odd = (num - 1) >> zeros;
+    'witness: for base in SMALL_PRIMES {
+        let mut xx = pow_mod(base, odd, num);
+        if xx == 1 || xx == num - 1 {
+            continue;
+        }
+        for _ in 1..zeros {
+            xx = mul_mod(xx, xx, num);
+            if xx == num - 1 {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+/// Pollard's rho with Brent's cycle detection, `num` must be composite
+fn find_divisor(num: u128) -> u128 {
+    for cc in 1.. {
+        let step = |xx| add_mod(mul_mod(xx, xx, num), cc, num);
+        let mut fast = 2;
+        let mut divisor = 1;
+        let mut limit = 1;
+        while divisor == 1 {
+            let slow = fast;
+            for _ in 0..limit {
+                fast = step(fast);
+                divisor = gcd(slow.abs_diff(fast), num);
+                if divisor != 1 {
+                    break;
+                }
+            }
+            limit *= 2;
+        }
+        if divisor != num {
+            return divisor;
+        }
+    }
+    unreachable!()
+}
+
+/// Prime factorization with multiplicities, sorted by the prime
+pub fn factor(num: u128) -> Vec<(u128, u32)> {
+    assert!(num != 0, "cannot factor zero");
+    let mut primes = Vec::new();
+    let mut rest = num;
+    for prime in (2..1 << 12).filter(|&pp| is_prime(pp)) {
+        while rest.is_multiple_of(prime) {
+            primes.push(prime);
+            rest /= prime;
+        }
+    }
+    let mut stack = vec![rest];
+    while let Some(num) = stack.pop() {
+        if num == 1 {
+            continue;
+        } else if is_prime(num) {
+            primes.push(num);
+        } else {
+            let divisor = find_divisor(num);
+            stack.push(divisor);
+            stack.push(num / divisor);
+        }
+    }
+    primes.sort();
+    let mut out: Vec<(u128, u32)> = Vec::new();
+    for prime in primes {
+        match out.last_mut() {
+            Some((last, count)) if *last == prime => *count += 1,
+            _ => out.push((prime, 1)),
+        }
+    }
+    out
+}
+
+/// Multiplicative order of `x` modulo an irreducible polynomial other than
+/// `x`, this divides `2^degree - 1` and is equal to it for primitive ones
+pub fn order_of_x(irreducible: &Poly) -> u128 {
+    let degree = irreducible.degree().expect("zero polynomial");
+    assert!(
+        (1..=128).contains(&degree) && irreducible.coeff(0),
+        "polynomial must have a degree of 1 to 128 and not be divisible by x"
+    );
+    let group = u128::MAX >> (128 - degree);
+    let one = Poly::one();
+    let mut order = group;
+    for (prime, _) in factor(group) {
+        while order.is_multiple_of(prime)
+            && Poly::x().powmod(order / prime, irreducible) == one
+        {
+            order /= prime;
+        }
+    }
+    order
+}
+
+/// Order of `x` modulo `poly^mult` for an irreducible `poly`
+fn primary_period(poly: &Poly, mult: u32) -> u128 {
+    order_of_x(poly) << (mult as u128).next_power_of_two().trailing_zeros()
+}
+
+/// Smallest `(tail, period)` such that `x^(tail + period) == x^tail`
+/// modulo `poly`. For the annihilator of a state this is the number of
+/// steps before it enters a cycle and the length of the cycle.
+pub fn x_period(poly: &Poly) -> (usize, u128) {
+    let mut tail = 0;
+    let mut period = 1;
+    for (factor, mult) in poly.factor() {
+        if factor == Poly::x() {
+            tail = mult as usize;
+        } else {
+            period = lcm(period, primary_period(&factor, mult));
+        }
+    }
+    (tail, period)
+}
+
+/// Number of steps before `state` enters a cycle and the cycle length
+pub fn state_period(matrix: &BitMatrixDyn, state: &[u64]) -> (usize, u128) {
+    x_period(&matrix.annihilator(state))
+}
+
+/// States on cycles of the same length
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cycles {
+    pub period: u128,
+    /// Number of states on these cycles
+    pub states: u128,
+    /// Number of distinct cycles
+    pub count: u128,
+}
+
+/// Every state reaches one of the cycles after at most `tail` steps, the
+/// rest of the states never come back
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleStructure {
+    pub tail: usize,
+    /// Sorted by the period
+    pub cycles: Vec<Cycles>,
+}
+
+impl CycleStructure {
+    /// The period of the whole map, the lcm of all cycle lengths
+    pub fn period(&self) -> u128 {
+        self.cycles
+            .iter()
+            .fold(1, |acc, cycles| lcm(acc, cycles.period))
+    }
+}
+
+/// Cycle structure of the linear map `state * matrix`.
+///
+/// The states on cycles split into parts for each irreducible factor `f`
+/// of the minimal polynomial. In the part of `f`, the states annihilated by
+/// `f^mult` but not `f^(mult - 1)` have a period of `order_of_x(f)` times
+/// the smallest power of two not below `mult`, and their count follows from
+/// the ranks of `f(matrix)^mult`. The state counts are `u128`, so the
+/// matrix can have at most 128 rows.
+pub fn cycle_structure(matrix: &BitMatrixDyn) -> CycleStructure {
+    let size = matrix.rows();
+    assert!(size <= 128, "state counts of more than 128 bits do not fit");
+    // (period, states) options for each factor
+    let mut parts = Vec::new();
+    let mut tail = 0;
+    for (factor, mult) in matrix.minpoly().factor() {
+        if factor == Poly::x() {
+            tail = mult as usize;
+            continue;
+        }
+        let base = matrix.eval_poly(&factor);
+        let mut power = BitMatrixDyn::eye(size);
+        let mut options = vec![(1, 1)];
+        let mut prev_dim = 0;
+        for mult in 1..=mult {
+            power = &power * &base;
+            let dim = size - power.rank();
+            options.push((
+                primary_period(&factor, mult),
+                pow2_diff(dim, prev_dim),
+            ));
+            prev_dim = dim;
+        }
+        parts.push(options);
+    }
+
+    let mut combined = vec![(1, 1)];
+    for options in parts {
+        let mut next = Vec::new();
+        for &(period, states) in combined.iter() {
+            for &(part_period, part_states) in options.iter() {
+                let states = u128::checked_mul(states, part_states)
+                    .expect("state count overflows u128");
+                next.push((lcm(period, part_period), states));
+            }
+        }
+        combined = next;
+    }
+    combined.sort();
+    let mut cycles: Vec<Cycles> = Vec::new();
+    for (period, states) in combined {
+        match cycles.last_mut() {
+            Some(last) if last.period == period => last.states += states,
+            _ => cycles.push(Cycles {
+                period,
+                states,
+                count: 0,
+            }),
+        }
+    }
+    for cycles in cycles.iter_mut() {
+        cycles.count = cycles.states / cycles.period;
+    }
+    CycleStructure { tail, cycles }
+}
+
+/// `2^hi - 2^lo`, wrapping so that `2^128 - 1` still fits
+fn pow2_diff(hi: usize, lo: usize) -> u128 {
+    let high = if hi == 128 { 0 } else { 1u128 << hi };
+    high.wrapping_sub(1 << lo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cycle_structure, factor, order_of_x, state_period, Cycles};
+    use crate::matrix_dyn::BitMatrixDyn;
+    use crate::poly::Poly;
+    use crate::xorshift128::XorShift128Rng;
+
+    #[test]
+    fn test_factor() {
+        assert_eq!(factor(1), []);
+        assert_eq!(factor(360), [(2, 3), (3, 2), (5, 1)]);
+        let factors = factor(u128::MAX);
+        let primes: Vec<u128> = factors.iter().map(|&(pp, _)| pp).collect();
+        assert_eq!(
+            primes,
+            [3, 5, 17, 257, 641, 65537, 274177, 6700417, 67280421310721]
+        );
+        // (2^61 - 1) times two primes just above 2^31 and 2^33
+        let (pp, qq, rr) = ((1 << 61) - 1, 2147483659, 8589934609);
+        assert_eq!(factor(pp * qq * rr), [(qq, 1), (rr, 1), (pp, 1)]);
+    }
+
+    #[test]
+    fn test_cycle_structure() {
+        // x^4 + x^3 + x^2 + x + 1 divides x^5 + 1, so x has order 5
+        assert_eq!(order_of_x(&Poly::from_words(&[0b11111])), 5);
+
+        let matrix = BitMatrixDyn::from(XorShift128Rng::build_matrix());
+        let cycles = cycle_structure(&matrix);
+        assert_eq!(cycles.tail, 0);
+        assert_eq!(
+            cycles.cycles,
+            [
+                Cycles {
+                    period: 1,
+                    states: 1,
+                    count: 1
+                },
+                Cycles {
+                    period: u128::MAX,
+                    states: u128::MAX,
+                    count: 1
+                },
+            ]
+        );
+
+        // identity on 3 bits plus a swap of 2 bits
+        let swap = BitMatrixDyn::from_rows(2, [&[0b10][..], &[0b01]]);
+        let matrix = BitMatrixDyn::block_diag(&[&BitMatrixDyn::eye(3), &swap]);
+        let cycles = cycle_structure(&matrix);
+        assert_eq!(cycles.cycles.len(), 2);
+        assert_eq!((cycles.cycles[0].states, cycles.cycles[0].count), (16, 16));
+        assert_eq!((cycles.cycles[1].states, cycles.cycles[1].count), (16, 8));
+        assert_eq!(state_period(&matrix, &[0b01000]), (0, 2));
+        assert_eq!(state_period(&matrix, &[0b11000]), (0, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "do not fit")]
+    fn test_cycle_structure_too_large() {
+        cycle_structure(&BitMatrixDyn::eye(130));
+    }
+}
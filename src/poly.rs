@@ -0,0 +1,451 @@
+use crate::splitmix::splitmix64;
+use core::fmt;
+use core::ops::BitXor;
+
+/// Polynomial over GF(2), bit `ii` of the words is the coefficient of `x^ii`.
+/// Trailing zero words are never stored, so equal polynomials compare equal.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Poly(Vec<u64>);
+
+impl fmt::Debug for Poly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Poly({})", self)
+    }
+}
+
+impl fmt::Display for Poly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(degree) = self.degree() else {
+            return f.write_str("0");
+        };
+        let mut first = true;
+        for ii in (0..=degree).rev().filter(|&ii| self.coeff(ii)) {
+            if !first {
+                f.write_str(" + ")?;
+            }
+            first = false;
+            match ii {
+                0 => f.write_str("1")?,
+                1 => f.write_str("x")?,
+                _ => write!(f, "x^{}", ii)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Poly {
+    pub fn zero() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn one() -> Self {
+        Self::monomial(0)
+    }
+
+    pub fn x() -> Self {
+        Self::monomial(1)
+    }
+
+    /// `x^degree`
+    pub fn monomial(degree: usize) -> Self {
+        let mut words = vec![0; degree / 64 + 1];
+        words[degree / 64] = 1 << (degree % 64);
+        Self(words)
+    }
+
+    /// Polynomial with the coefficients taken from the bits of `words`
+    pub fn from_words(words: &[u64]) -> Self {
+        let mut out = Self(words.to_vec());
+        out.normalize();
+        out
+    }
+
+    pub fn words(&self) -> &[u64] {
+        &self.0
+    }
+
+    fn normalize(&mut self) {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Degree of the polynomial, `None` for zero
+    pub fn degree(&self) -> Option<usize> {
+        let last = self.0.last()?;
+        Some(self.0.len() * 64 - 1 - last.leading_zeros() as usize)
+    }
+
+    pub fn coeff(&self, power: usize) -> bool {
+        self.0
+            .get(power / 64)
+            .is_some_and(|word| (word >> (power % 64)) & 1 != 0)
+    }
+
+    pub fn set_coeff(&mut self, power: usize, value: bool) {
+        if self.0.len() <= power / 64 {
+            if !value {
+                return;
+            }
+            self.0.resize(power / 64 + 1, 0);
+        }
+        let mask = 1 << (power % 64);
+        if value {
+            self.0[power / 64] |= mask;
+        } else {
+            self.0[power / 64] &= !mask;
+            self.normalize();
+        }
+    }
+
+    /// `self * x^shift`
+    pub fn shl(&self, shift: usize) -> Self {
+        if self.is_zero() {
+            return Self::zero();
+        }
+        let (words, rem) = (shift / 64, shift % 64);
+        let mut out = vec![0; self.0.len() + words + 1];
+        for (ii, &word) in self.0.iter().enumerate() {
+            out[ii + words] ^= word << rem;
+            if rem != 0 {
+                out[ii + words + 1] ^= word >> (64 - rem);
+            }
+        }
+        let mut out = Self(out);
+        out.normalize();
+        out
+    }
+
+    /// `self ^= other * x^shift`
+    fn add_shifted(&mut self, other: &Self, shift: usize) {
+        let (words, rem) = (shift / 64, shift % 64);
+        let len = other.0.len() + words + 1;
+        if self.0.len() < len {
+            self.0.resize(len, 0);
+        }
+        for (ii, &word) in other.0.iter().enumerate() {
+            self.0[ii + words] ^= word << rem;
+            if rem != 0 {
+                self.0[ii + words + 1] ^= word >> (64 - rem);
+            }
+        }
+        self.normalize();
+    }
+
+    /// Quotient and remainder of the polynomial division
+    pub fn divrem(&self, divisor: &Self) -> (Self, Self) {
+        let div_degree = divisor.degree().expect("division by zero");
+        let mut quot = Self::zero();
+        let mut rem = self.clone();
+        while let Some(degree) = rem.degree() {
+            if degree < div_degree {
+                break;
+            }
+            let shift = degree - div_degree;
+            rem.add_shifted(divisor, shift);
+            quot.set_coeff(shift, true);
+        }
+        (quot, rem)
+    }
+
+    pub fn gcd(&self, other: &Self) -> Self {
+        let mut aa = self.clone();
+        let mut bb = other.clone();
+        while !bb.is_zero() {
+            let rem = &aa % &bb;
+            aa = bb;
+            bb = rem;
+        }
+        aa
+    }
+
+    pub fn lcm(&self, other: &Self) -> Self {
+        if self.is_zero() || other.is_zero() {
+            return Self::zero();
+        }
+        &(self / &self.gcd(other)) * other
+    }
+
+    /// `self^exp mod modulus`, by square and multiply
+    pub fn powmod(&self, exp: u128, modulus: &Self) -> Self {
+        let mut out = &Self::one() % modulus;
+        let mut base = self % modulus;
+        let mut exp = exp;
+        while exp > 0 {
+            if exp & 1 != 0 {
+                out = &(&out * &base) % modulus;
+            }
+            base = &(&base * &base) % modulus;
+            exp >>= 1;
+        }
+        out
+    }
+
+    /// Evaluate the polynomial at a linear map given as `step`, applied to
+    /// `start` with Horner's method. With `start` being the identity matrix
+    /// and `step` multiplying by `M` this computes `p(M)`, with a state
+    /// vector and `step` advancing the generator this jumps the state.
+    pub fn apply<T>(&self, start: T, mut step: impl FnMut(T) -> T) -> T
+    where
+        T: Copy + Default + BitXor<Output = T>,
+    {
+        let mut out = T::default();
+        let Some(degree) = self.degree() else {
+            return out;
+        };
+        for ii in (0..=degree).rev() {
+            out = step(out);
+            if self.coeff(ii) {
+                out = out ^ start;
+            }
+        }
+        out
+    }
+
+    /// Formal derivative, only the odd powers survive in GF(2)
+    pub fn derivative(&self) -> Self {
+        let mut out = Self::zero();
+        for ii in (1..=self.degree().unwrap_or(0)).step_by(2) {
+            if self.coeff(ii) {
+                out.set_coeff(ii - 1, true);
+            }
+        }
+        out
+    }
+
+    /// Square root of a polynomial with only even powers
+    fn sqrt(&self) -> Self {
+        let mut out = Self::zero();
+        for ii in (0..=self.degree().unwrap_or(0)).step_by(2) {
+            if self.coeff(ii) {
+                out.set_coeff(ii / 2, true);
+            }
+        }
+        out
+    }
+
+    /// Factorization into irreducible polynomials with their multiplicities,
+    /// sorted by degree and then by value
+    pub fn factor(&self) -> Vec<(Self, u32)> {
+        assert!(!self.is_zero(), "cannot factor zero");
+        let mut out = Vec::new();
+        for (poly, mult) in self.squarefree() {
+            for (poly, degree) in poly.distinct_degree() {
+                for factor in poly.equal_degree(degree) {
+                    out.push((factor, mult));
+                }
+            }
+        }
+        out.sort_by(|(aa, _), (bb, _)| {
+            aa.degree()
+                .cmp(&bb.degree())
+                .then(aa.0.iter().rev().cmp(bb.0.iter().rev()))
+        });
+        out
+    }
+
+    pub fn is_irreducible(&self) -> bool {
+        let factors = self.factor();
+        factors.len() == 1 && factors[0].1 == 1
+    }
+
+    /// Squarefree factors with their multiplicities
+    fn squarefree(&self) -> Vec<(Self, u32)> {
+        let mut out = Vec::new();
+        let one = Self::one();
+        let mut rest = self.gcd(&self.derivative());
+        let mut part = self / &rest;
+        let mut mult = 1;
+        while part != one {
+            let common = part.gcd(&rest);
+            let factor = &part / &common;
+            if factor != one {
+                out.push((factor, mult));
+            }
+            rest = &rest / &common;
+            part = common;
+            mult += 1;
+        }
+        // what is left is a perfect square
+        if rest != one {
+            for (factor, mult) in rest.sqrt().squarefree() {
+                out.push((factor, mult * 2));
+            }
+        }
+        out
+    }
+
+    /// Split a squarefree polynomial into products of the irreducible
+    /// factors of the same degree
+    fn distinct_degree(&self) -> Vec<(Self, usize)> {
+        let mut out = Vec::new();
+        let one = Self::one();
+        let mut rest = self.clone();
+        // x^(2^degree) mod rest
+        let mut power = &Self::x() % &rest;
+        let mut degree = 0;
+        while rest.degree().unwrap_or(0) >= 2 * (degree + 1) {
+            degree += 1;
+            power = &(&power * &power) % &rest;
+            let common = rest.gcd(&(&power + &Self::x()));
+            if common != one {
+                rest = &rest / &common;
+                power = &power % &rest;
+                out.push((common, degree));
+            }
+        }
+        if let Some(degree @ 1..) = rest.degree() {
+            out.push((rest, degree));
+        }
+        out
+    }
+
+    /// Cantor-Zassenhaus split of a product of irreducible polynomials of
+    /// the given degree, using the trace map in place of the exponentiation
+    /// by `(q - 1) / 2`
+    fn equal_degree(&self, degree: usize) -> Vec<Self> {
+        let total = self.degree().unwrap();
+        if total == degree {
+            return vec![self.clone()];
+        }
+        let mut seed = total as u64;
+        loop {
+            let mut guess = Self::zero();
+            for ii in 0..total {
+                guess.set_coeff(ii, splitmix64(&mut seed) & 1 != 0);
+            }
+            let mut trace = guess.clone();
+            let mut power = guess;
+            for _ in 1..degree {
+                power = &(&power * &power) % self;
+                trace = &trace + &power;
+            }
+            let common = self.gcd(&trace);
+            if let Some(1..) = common.degree().filter(|&d| d < total) {
+                let mut out = common.equal_degree(degree);
+                out.extend((self / &common).equal_degree(degree));
+                return out;
+            }
+        }
+    }
+}
+
+impl core::ops::Add for &Poly {
+    type Output = Poly;
+    fn add(self, other: Self) -> Poly {
+        let mut out = self.clone();
+        out.add_shifted(other, 0);
+        out
+    }
+}
+
+impl core::ops::Mul for &Poly {
+    type Output = Poly;
+    fn mul(self, other: Self) -> Poly {
+        let (short, long) = if self.0.len() < other.0.len() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut out = Poly::zero();
+        for (ii, &word) in short.0.iter().enumerate() {
+            let mut word = word;
+            while word != 0 {
+                let bit = word.trailing_zeros() as usize;
+                out.add_shifted(long, ii * 64 + bit);
+                word &= word - 1;
+            }
+        }
+        out
+    }
+}
+
+impl core::ops::Div for &Poly {
+    type Output = Poly;
+    fn div(self, other: Self) -> Poly {
+        self.divrem(other).0
+    }
+}
+
+impl core::ops::Rem for &Poly {
+    type Output = Poly;
+    fn rem(self, other: Self) -> Poly {
+        self.divrem(other).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Poly;
+
+    #[test]
+    fn test_arith() {
+        let aa = Poly::from_words(&[0b1001, 1 << 3]); // x^67 + x^3 + 1
+        let bb = Poly::from_words(&[0b111]); // x^2 + x + 1
+        let cc = Poly::from_words(&[0b11]); // x + 1
+        assert_eq!(aa.to_string(), "x^67 + x^3 + 1");
+        assert_eq!(aa.degree(), Some(67));
+
+        let prod = &(&aa * &bb) + &cc;
+        assert_eq!(prod.divrem(&bb), (aa.clone(), cc.clone()));
+        assert_eq!(&(&aa * &bb) * &cc, &aa * &(&bb * &cc));
+        assert_eq!((&aa * &bb).gcd(&(&bb * &cc)), bb);
+        assert_eq!(bb.lcm(&cc), &bb * &cc);
+        assert_eq!(aa.shl(70), &aa * &Poly::monomial(70));
+        assert_eq!(&aa + &aa, Poly::zero());
+    }
+
+    #[test]
+    fn test_powmod_apply() {
+        // x^31 + x^3 + 1 is primitive, the order of x is 2^31 - 1
+        let modulus = Poly::from_words(&[(1 << 31) | 0b1001]);
+        let x = Poly::x();
+        assert_eq!(x.powmod((1 << 31) - 1, &modulus), Poly::one());
+        assert_ne!(x.powmod(((1 << 31) - 1) / 7, &modulus), Poly::one());
+        let mut naive = Poly::one();
+        for _ in 0..100 {
+            naive = &naive.shl(1) % &modulus;
+        }
+        assert_eq!(x.powmod(100, &modulus), naive);
+
+        // x^3 + x + 1 applied to `u32 << 1`
+        let poly = Poly::from_words(&[0b1011]);
+        let value: u32 = 0x1337;
+        let expected = (value << 3) ^ (value << 1) ^ value;
+        assert_eq!(poly.apply(value, |acc| acc << 1), expected);
+        assert_eq!(Poly::zero().apply(value, |acc| acc << 1), 0);
+    }
+
+    #[test]
+    fn test_factor() {
+        let x = Poly::x();
+        let x1 = Poly::from_words(&[0b11]); // x + 1
+        let p2 = Poly::from_words(&[0b111]); // x^2 + x + 1
+        let p3a = Poly::from_words(&[0b1011]); // x^3 + x + 1
+        let p3b = Poly::from_words(&[0b1101]); // x^3 + x^2 + 1
+        let p31 = Poly::from_words(&[(1 << 31) | 0b1001]);
+        let product = [&x, &x, &x1, &x1, &x1, &p2, &p3a, &p3b, &p3b, &p31]
+            .into_iter()
+            .fold(Poly::one(), |acc, poly| &acc * poly);
+        let factors = product.factor();
+        assert_eq!(
+            factors,
+            [
+                (x, 2),
+                (x1, 3),
+                (p2, 1),
+                (p3a, 1),
+                (p3b, 2),
+                (p31.clone(), 1)
+            ]
+        );
+        assert!(p31.is_irreducible());
+        assert!(!Poly::from_words(&[(1 << 31) | 0b11]).is_irreducible());
+        assert!(Poly::one().factor().is_empty());
+    }
+}
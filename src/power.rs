@@ -0,0 +1,77 @@
+use core::ops::Mul;
+
+/// The powers `M^(2^k)` of a matrix. Applying a jump of `steps` takes one
+/// vector multiply per set bit of `steps`, without building the matrix of
+/// the jump first, so jumps of any length are equally cheap.
+#[derive(Clone, Debug)]
+pub struct PowerTable<M> {
+    powers: Vec<M>,
+}
+
+impl<M: Copy + Mul<Output = M>> PowerTable<M> {
+    /// Table for jumps of less than `2^bits` steps, `steps` is a `u128` so
+    /// at most 128 powers are of any use
+    pub fn new(matrix: M, bits: u32) -> Self {
+        assert!(bits <= 128, "jumps are at most 128 bits long");
+        let mut powers = Vec::with_capacity(bits as usize);
+        let mut power = matrix;
+        for _ in 0..bits {
+            powers.push(power);
+            power = power * power;
+        }
+        Self { powers }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.powers.len() as u32
+    }
+
+    /// `M^(2^k)`
+    pub fn power(&self, k: u32) -> M {
+        self.powers[k as usize]
+    }
+
+    /// `vec * M^steps`
+    pub fn apply<V: Mul<M, Output = V>>(&self, vec: V, steps: u128) -> V {
+        assert!(
+            self.bits() >= 128 || steps >> self.bits() == 0,
+            "jump is too long for the table"
+        );
+        self.powers
+            .iter()
+            .enumerate()
+            .filter(|&(kk, _)| (steps >> kk) & 1 != 0)
+            .fold(vec, |vec, (_, &power)| vec * power)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PowerTable;
+    use crate::xorshift128::XorShift128Rng;
+
+    #[test]
+    fn test_apply() {
+        let matrix = XorShift128Rng::build_matrix();
+        let table = PowerTable::new(matrix, 128);
+        assert_eq!(table.power(5), matrix.pow(32));
+        let mut rng = XorShift128Rng {
+            state0: 0x1337133713371337,
+            state1: 0xdeadbeefdeadbeef,
+        };
+        let state = (rng.state1 as u128) << 64 | rng.state0 as u128;
+        for _ in 0..1000 {
+            rng.next();
+        }
+        let jumped = table.apply(state, 1000);
+        assert_eq!(jumped, (rng.state1 as u128) << 64 | rng.state0 as u128);
+        // the period is 2^128 - 1
+        assert_eq!(table.apply(jumped, u128::MAX), jumped);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 128 bits")]
+    fn test_too_many_bits() {
+        PowerTable::new(XorShift128Rng::build_matrix(), 130);
+    }
+}
@@ -1,4 +1,11 @@
+use crate::affine::AffineMap32;
+use crate::layout::{Field, VariableLayout};
 use crate::matrix::BitMatrix32 as M32;
+use crate::matrix_dyn::BitMatrixDyn;
+use crate::period::{cycle_structure, state_period, CycleStructure};
+use crate::poly::Poly;
+use crate::power::PowerTable;
+use crate::sym::{linear_fn, SymU32};
 
 /// Reference implementation of the WvsGlobal RNG used for scrolling
 ///
@@ -7,6 +14,7 @@ use crate::matrix::BitMatrix32 as M32;
 ///
 /// This is not designed to be fast, simplified, etc. This is simply designed
 /// to be a reference implementation to compare against
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Rand32Ref {
     seed1: u32,
     seed2: u32,
@@ -20,10 +28,11 @@ impl Rand32Ref {
     }
 
     pub fn seeded(seed1: u32, seed2: u32, seed3: u32) -> Self {
+        let [f1, f2, f3] = SEED_FORCED_BITS;
         Self {
-            seed1: seed1 | 0x100000,
-            seed2: seed2 | 0x1000,
-            seed3: seed3 | 0x10,
+            seed1: seed1 | f1,
+            seed2: seed2 | f2,
+            seed3: seed3 | f3,
         }
     }
 
@@ -40,26 +49,195 @@ impl Rand32Ref {
     }
 
     pub fn rand(&mut self) -> u32 {
-        self.seed1 = ((self.seed1 & 0xFFFFFFFE) << 12)
-            ^ ((self.seed1 & 0x7FFC0 ^ (self.seed1 >> 13)) >> 6);
-        self.seed2 = ((self.seed2 & 0xFFFFFFF8) << 4)
-            ^ (((self.seed2 >> 2) ^ self.seed2 & 0x3F800000) >> 23);
-        self.seed3 = ((self.seed3 & 0xFFFFFFF0) << 17)
-            ^ (((self.seed3 >> 3) ^ self.seed3 & 0x1FFFFF00) >> 8);
+        self.seed1 = step_s1(self.seed1);
+        self.seed2 = step_s2(self.seed2);
+        self.seed3 = step_s3(self.seed3);
         self.seed1 ^ self.seed2 ^ self.seed3
     }
+
+    /// Jump by `steps`, backwards for negative `steps`. Going backwards
+    /// leaves the never observed low bits of the seeds zero.
+    pub fn jump(&mut self, jumps: &Rand32Jumps, steps: i64) {
+        let seeds = [self.seed1, self.seed2, self.seed3];
+        [self.seed1, self.seed2, self.seed3] = jumps.apply(seeds, steps);
+    }
+
+    /// Advance the state by the distance the polynomials were built for,
+    /// see `rng_jump_poly`
+    pub fn jump_by(&mut self, polys: &[Poly; 3]) {
+        let [m1, m2, m3] = rng_matrix();
+        self.seed1 = polys[0].apply(self.seed1, |seed| seed * m1);
+        self.seed2 = polys[1].apply(self.seed2, |seed| seed * m2);
+        self.seed3 = polys[2].apply(self.seed3, |seed| seed * m3);
+    }
+}
+
+linear_fn! {
+    /// Rand32 step of seed1
+    pub fn step_s1 / step_s1_const (s): u32 {
+        ((s & 0xFFFFFFFE) << 12) ^ (((s & 0x7FFC0) ^ (s >> 13)) >> 6)
+    }
+}
+
+linear_fn! {
+    /// Rand32 step of seed2
+    pub fn step_s2 / step_s2_const (s): u32 {
+        ((s & 0xFFFFFFF8) << 4) ^ (((s >> 2) ^ (s & 0x3F800000)) >> 23)
+    }
+}
+
+linear_fn! {
+    /// Rand32 step of seed3
+    pub fn step_s3 / step_s3_const (s): u32 {
+        ((s & 0xFFFFFFF0) << 17) ^ (((s >> 3) ^ (s & 0x1FFFFF00)) >> 8)
+    }
+}
+
+/// Bits that `Rand32Ref::seeded` forces to one in each seed
+pub const SEED_FORCED_BITS: [u32; 3] = [0x100000, 0x1000, 0x10];
+
+/// `Rand32Ref::seeded` for each seed as an affine map. The tick count
+/// transform of `Rand32Ref::new` is an integer multiply and add, the carries
+/// make it nonlinear over GF(2), so it has no such map.
+pub fn rng_seed_map() -> [AffineMap32; 3] {
+    SEED_FORCED_BITS.map(AffineMap32::or)
 }
 
 /// Rand32 step for each state field, represented in terms of matrix transform
 pub fn rng_matrix() -> [M32; 3] {
-    use core::ops::{Shl, Shr};
-    let m1 = M32::eye().and(0xFFFFFFFE).shl(12)
-        ^ (M32::eye().and(0x7FFC0) ^ M32::eye().shr(13)).shr(6);
-    let m2 = M32::eye().and(0xFFFFFFF8).shl(4)
-        ^ (M32::eye().shr(2) ^ M32::eye().and(0x3F800000)).shr(23);
-    let m3 = M32::eye().and(0xFFFFFFF0).shl(17)
-        ^ (M32::eye().shr(3) ^ M32::eye().and(0x1FFFFF00)).shr(8);
-    [m1, m2, m3]
+    [
+        SymU32::derive(step_s1),
+        SymU32::derive(step_s2),
+        SymU32::derive(step_s3),
+    ]
+}
+
+/// `x^steps` modulo the characteristic polynomial of each state field step,
+/// evaluating these at the step matrices gives the jump matrices
+pub fn rng_jump_poly(steps: u128) -> [Poly; 3] {
+    rng_matrix().map(|matrix| Poly::x().powmod(steps, &matrix.charpoly()))
+}
+
+/// Cycle structure of each state field step. Every field has one long cycle
+/// and the zero state, the shifted out low bits feed into either of them.
+pub fn rng_cycle_structure() -> [CycleStructure; 3] {
+    rng_matrix().map(|matrix| cycle_structure(&BitMatrixDyn::from(matrix)))
+}
+
+/// Period of the generator for the states `Rand32Ref::seeded` produces,
+/// the fixed bits keep every field on its long cycle
+pub fn rng_period() -> u128 {
+    cycle_structure(&rng_state_matrix()).period()
+}
+
+/// Number of steps before the state enters a cycle and the cycle length
+pub fn rng_state_period(state: (u32, u32, u32)) -> (usize, u128) {
+    let vector = state.0 as u64 | (state.1 as u64) << 32;
+    state_period(&rng_state_matrix(), &[vector, state.2 as u64])
+}
+
+/// Rand32 step for the whole 96-bit state, seed1 is in the low bits
+pub fn rng_state_matrix() -> BitMatrixDyn {
+    let [m1, m2, m3] = rng_matrix().map(BitMatrixDyn::from);
+    BitMatrixDyn::block_diag(&[&m1, &m2, &m3])
+}
+
+/// Rand32 output `seed1 ^ seed2 ^ seed3` in terms of the 96-bit state
+pub fn rng_output_matrix() -> BitMatrixDyn {
+    let eye = BitMatrixDyn::eye(32);
+    BitMatrixDyn::vstack(&[&eye, &eye, &eye])
+}
+
+/// Rand32 outputs `1..=count` in terms of the 96-bit state, output `ii` is
+/// placed at columns `32 * ii..32 * (ii + 1)`
+pub fn rng_outputs_matrix(count: usize) -> BitMatrixDyn {
+    let step = rng_state_matrix();
+    let output = rng_output_matrix();
+    let mut state = step.clone();
+    let mut outputs = Vec::with_capacity(count);
+    for _ in 0..count {
+        outputs.push(&state * &output);
+        state = &state * &step;
+    }
+    BitMatrixDyn::hstack(&outputs.iter().collect::<Vec<_>>())
+}
+
+/// Unknowns of the 96-bit Rand32 state. The low bits of each seed are
+/// shifted out by the first step and never affect the outputs.
+pub fn rng_layout() -> VariableLayout {
+    let [s1, s2, s3] = RNG_UNKNOWN_BITS.map(|bits| bits as u64);
+    VariableLayout::new(vec![
+        Field::new("seed1", 32, s1),
+        Field::new("seed2", 32, s2),
+        Field::new("seed3", 32, s3),
+    ])
+}
+
+/// Bits of each seed that are unknowns of `rng_layout`
+pub const RNG_UNKNOWN_BITS: [u32; 3] = [!0x1, !0x7, !0xf];
+
+/// Number of unknowns of `rng_layout`
+pub const RNG_UNKNOWNS: usize = (RNG_UNKNOWN_BITS[0].count_ones()
+    + RNG_UNKNOWN_BITS[1].count_ones()
+    + RNG_UNKNOWN_BITS[2].count_ones())
+    as usize;
+
+/// Position in the 96-bit state of each unknown of `rng_layout`, in the
+/// order the layout packs them
+pub(crate) const RNG_UNKNOWN_POSITIONS: [usize; RNG_UNKNOWNS] =
+    rng_unknown_positions();
+
+const fn rng_unknown_positions() -> [usize; RNG_UNKNOWNS] {
+    let mut out = [0; RNG_UNKNOWNS];
+    let mut ii = 0;
+    let mut pos = 0;
+    while pos < 96 {
+        if (RNG_UNKNOWN_BITS[pos / 32] >> (pos % 32)) & 1 != 0 {
+            out[ii] = pos;
+            ii += 1;
+        }
+        pos += 1;
+    }
+    out
+}
+
+/// The first `N` outputs of a generator whose state is only unknown `ii`
+/// of `rng_layout`, which is the row of that unknown in the output matrix
+pub(crate) const fn rng_unknown_outputs<const N: usize>(ii: usize) -> [u32; N] {
+    let pos = RNG_UNKNOWN_POSITIONS[ii];
+    let seed = pos / 32;
+    let mut value = 1 << (pos % 32);
+
+    let mut out = [0; N];
+    let mut jj = 0;
+    while jj < N {
+        value = match seed {
+            0 => step_s1_const(value),
+            1 => step_s2_const(value),
+            _ => step_s3_const(value),
+        };
+        out[jj] = value;
+        jj += 1;
+    }
+    out
+}
+
+/// Convert a vector of unknowns from `rng_layout` into a Rand32 state
+pub fn layout_to_state(
+    layout: &VariableLayout,
+    vector: u128,
+) -> (u32, u32, u32) {
+    let [s1, s2, s3] = layout.unpack(vector);
+    (s1 as u32, s2 as u32, s3 as u32)
+}
+
+/// Convert a Rand32 state into a vector of unknowns from `rng_layout`
+pub fn state_to_layout(
+    layout: &VariableLayout,
+    state: (u32, u32, u32),
+) -> u128 {
+    let (s1, s2, s3) = state;
+    layout.pack(&[s1 as u64, s2 as u64, s3 as u64])
 }
 
 /// Rand32 back step for each state field, represented in terms of matrix
@@ -82,3 +260,146 @@ pub fn rng_back_matrix() -> [M32; 3] {
         m3.inv().unwrap().shr(4).shl(4),
     ]
 }
+
+/// Power tables of the three seed matrices in both directions, for jumps
+/// of varying length
+#[derive(Clone, Debug)]
+pub struct Rand32Jumps {
+    forward: [PowerTable<M32>; 3],
+    backward: [PowerTable<M32>; 3],
+}
+
+impl Rand32Jumps {
+    pub fn new() -> Self {
+        let table = |matrix| PowerTable::new(matrix, 64);
+        Self {
+            forward: rng_matrix().map(table),
+            backward: rng_back_matrix().map(table),
+        }
+    }
+
+    /// Jump each seed by `steps`, backwards for negative `steps`
+    pub fn apply<V>(&self, seeds: [V; 3], steps: i64) -> [V; 3]
+    where
+        V: core::ops::Mul<M32, Output = V>,
+    {
+        let tables = if steps < 0 {
+            &self.backward
+        } else {
+            &self.forward
+        };
+        let steps = steps.unsigned_abs() as u128;
+        let [s1, s2, s3] = seeds;
+        [
+            tables[0].apply(s1, steps),
+            tables[1].apply(s2, steps),
+            tables[2].apply(s3, steps),
+        ]
+    }
+
+    /// Matrices of the jump by `steps` for each seed
+    pub fn matrices(&self, steps: i64) -> [M32; 3] {
+        self.apply([M32::eye(); 3], steps)
+    }
+}
+
+impl Default for Rand32Jumps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        rng_back_matrix, rng_cycle_structure, rng_jump_poly, rng_layout,
+        rng_matrix, rng_period, rng_seed_map, rng_state_period, Rand32Jumps,
+        Rand32Ref, RNG_UNKNOWN_POSITIONS,
+    };
+
+    #[test]
+    fn test_unknown_positions() {
+        // the const packing order must follow the layout
+        assert_eq!(rng_layout().state_positions(), RNG_UNKNOWN_POSITIONS);
+    }
+
+    #[test]
+    fn test_jump() {
+        let mut rng = Rand32Ref::new(0x13371337);
+        let mut jumped = Rand32Ref::from_state(rng.state());
+        for _ in 0..0x1337 {
+            rng.rand();
+        }
+        jumped.jump_by(&rng_jump_poly(0x1337));
+        assert_eq!(rng.state(), jumped.state());
+        assert_eq!(rng.rand(), jumped.rand());
+    }
+
+    #[test]
+    fn test_power_table_jump() {
+        let jumps = Rand32Jumps::new();
+        let mut rng = Rand32Ref::new(0x13371337);
+        let mut jumped = Rand32Ref::from_state(rng.state());
+        for _ in 0..0x1337 {
+            rng.rand();
+        }
+        jumped.jump(&jumps, 0x1337);
+        assert_eq!(rng.state(), jumped.state());
+
+        // alternating lengths, the way back ends in the same outputs
+        let mut back = Rand32Ref::new(0x13371337);
+        let expected: Vec<u32> = (0..4).map(|_| back.rand()).collect();
+        for steps in [0x1337, 5, 1 << 40] {
+            jumped.jump(&jumps, steps);
+        }
+        jumped.jump(&jumps, -(0x1337 + 0x1337 + 5 + (1 << 40)));
+        let outputs: Vec<u32> = (0..4).map(|_| jumped.rand()).collect();
+        assert_eq!(outputs, expected);
+    }
+
+    #[test]
+    fn test_period() {
+        let periods = rng_cycle_structure().map(|cycles| cycles.period());
+        assert_eq!(periods, [(1 << 31) - 1, (1 << 29) - 1, (1 << 28) - 1]);
+        let period = rng_period();
+        assert_eq!(period, periods.iter().product());
+
+        let rng = Rand32Ref::new(0x13371337);
+        // the low bits of the seeds are shifted out on the first step
+        assert_eq!(rng_state_period(rng.state()), (1, period));
+        // without the fixed bits, a field can fall into the zero state
+        assert_eq!(rng_state_period((1, 0, 0x10)), (1, periods[2]));
+    }
+
+    #[test]
+    fn test_derived_matrices() {
+        use crate::affine::AffineMap32;
+        use crate::matrix::BitMatrix32 as M32;
+        use crate::rand32_rev::{prev_s1, prev_s2, prev_s3};
+        use crate::sym::SymU32;
+        // the same steps written out as matrix operations
+        let eye = M32::eye();
+        let m1 = (eye.and(0xFFFFFFFE) << 12)
+            ^ ((eye.and(0x7FFC0) ^ (eye >> 13)) >> 6);
+        let m2 = (eye.and(0xFFFFFFF8) << 4)
+            ^ (((eye >> 2) ^ eye.and(0x3F800000)) >> 23);
+        let m3 = (eye.and(0xFFFFFFF0) << 17)
+            ^ (((eye >> 3) ^ eye.and(0x1FFFFF00)) >> 8);
+        assert_eq!(rng_matrix(), [m1, m2, m3]);
+
+        let prev = [
+            SymU32::derive(prev_s1),
+            SymU32::derive(prev_s2),
+            SymU32::derive(prev_s3),
+        ];
+        assert_eq!(prev, rng_back_matrix());
+
+        let seeded = |seed| Rand32Ref::seeded(seed, seed, seed).state();
+        let maps = [
+            AffineMap32::from_fn(|seed| seeded(seed).0),
+            AffineMap32::from_fn(|seed| seeded(seed).1),
+            AffineMap32::from_fn(|seed| seeded(seed).2),
+        ];
+        assert_eq!(maps, rng_seed_map());
+    }
+}
@@ -1,9 +1,12 @@
+use crate::rand32::{RNG_UNKNOWN_BITS, SEED_FORCED_BITS};
+use crate::sym::Word;
+
 // s = vuts rqpo nmlk jihg fedc ba98 7654 3210
 // XOR jihg fedc ba98 7654 321
 //                            v utsr qpon mlkj
 //                            i hgfe dcba 9876
 /// Compute previous value for seed1 in Rand32
-pub fn prev_s1(s: u32) -> u32 {
+pub fn prev_s1<T: Word<u32>>(s: T) -> T {
     let bits_19_01 = s >> 13;
     let bits_31_20 = ((bits_19_01 >> 6) & 0xfff) ^ (s >> 1);
     (bits_31_20 << 20) | (bits_19_01 << 1)
@@ -14,7 +17,7 @@ pub fn prev_s1(s: u32) -> u32 {
 //                                    vut srqp
 //                                    tsr qpon
 /// Compute previous value for seed2 in Rand32
-pub fn prev_s2(s: u32) -> u32 {
+pub fn prev_s2<T: Word<u32>>(s: T) -> T {
     let bits_27_03 = s >> 7;
     let bits_29_28 = ((s >> 30) ^ (s >> 3)) & 0b11;
     let bits_31_30 = (s >> 5) ^ bits_29_28;
@@ -26,14 +29,14 @@ pub fn prev_s2(s: u32) -> u32 {
 //                  v utsr qpon mlkj ihgf edcb
 //                  s rqpo nmlk jihg fedc ba98
 /// Compute previous value for seed3 in Rand32
-pub fn prev_s3(s: u32) -> u32 {
+pub fn prev_s3<T: Word<u32>>(s: T) -> T {
     let bits_14_04 = s >> 21;
     let mut window = s >> 4;
-    let mut bits_31_15 = 0;
+    let mut bits_31_15 = T::default();
     let mut chunk = (bits_14_04 >> 8) & 0b111;
     for _ in 0..6 {
         chunk = (window ^ chunk) & 0b111;
-        window >>= 3;
+        window = window >> 3;
         bits_31_15 = (bits_31_15 >> 3) | (chunk << 29);
     }
     (bits_31_15 << 1) | (bits_14_04 << 4)
@@ -93,9 +96,10 @@ pub fn seed_to_timestamp(s: u32) -> u32 {
 /// Find the number of steps and the timestamp that produce a given Rand32 state
 pub fn find_rng_timestamp(state: (u32, u32, u32)) -> (usize, [u32; 2]) {
     let (mut s1, mut s2, mut s3) = state;
-    let m1 = !0x100001;
-    let m2 = !0x1007;
-    let m3 = !0x1f;
+    // the bits that reach the outputs and are not forced by the seeding,
+    // these are equal in all three seeds at the start
+    let [m1, m2, m3]: [u32; 3] =
+        core::array::from_fn(|ii| RNG_UNKNOWN_BITS[ii] & !SEED_FORCED_BITS[ii]);
     let m12 = m1 & m2;
     let m13 = m1 & m3;
 
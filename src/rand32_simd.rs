@@ -1,12 +1,15 @@
-use crate::matrix::BitMatrix32 as M32;
+use crate::matrix::MatrixTable;
+use crate::rand32::Rand32Jumps;
 use core::simd::{LaneCount, Simd, SupportedLaneCount};
 
 pub struct Rand32Simd<const LANES: usize>
 where
     LaneCount<LANES>: SupportedLaneCount,
 {
-    last_jump: isize,
-    last_delta: [M32; 3],
+    jumps: Rand32Jumps,
+    /// Byte tables of the last jump, repeating it takes four lookups per
+    /// seed
+    last_jump: Option<(isize, [MatrixTable<u32>; 3])>,
     seed1: Simd<u32, LANES>,
     seed2: Simd<u32, LANES>,
     seed3: Simd<u32, LANES>,
@@ -32,37 +35,28 @@ where
         };
 
         Self {
-            last_jump: 0,
-            last_delta: [M32::eye(); 3],
+            jumps: Rand32Jumps::new(),
+            last_jump: None,
             seed1: seed_from_params(s1, m1.pow(interval)),
             seed2: seed_from_params(s2, m2.pow(interval)),
             seed3: seed_from_params(s3, m3.pow(interval)),
         }
     }
 
+    /// Jump every lane by `steps`, backwards for negative `steps`. Jumps
+    /// of the same length as the last one are the cheapest.
     pub fn jump(&mut self, steps: isize) {
-        if self.last_jump != steps {
-            self.last_jump = steps;
-
-            if steps < 0 {
-                let steps = steps
-                    .checked_neg()
-                    .expect("what are you doing at MIN_INT?")
-                    as usize;
-
-                self.last_delta =
-                    crate::rand32::rng_back_matrix().map(|m| m.pow(steps));
-            } else {
-                let steps = steps as usize;
-
-                self.last_delta =
-                    crate::rand32::rng_matrix().map(|m| m.pow(steps));
+        let tables = match &self.last_jump {
+            Some((last, tables)) if *last == steps => tables,
+            _ => {
+                let matrices = self.jumps.matrices(steps as i64);
+                let tables = matrices.map(|matrix| matrix.table());
+                &self.last_jump.insert((steps, tables)).1
             }
-        }
-
-        self.seed1 = self.seed1 * self.last_delta[0];
-        self.seed2 = self.seed2 * self.last_delta[1];
-        self.seed3 = self.seed3 * self.last_delta[2];
+        };
+        self.seed1 *= &tables[0];
+        self.seed2 *= &tables[1];
+        self.seed3 *= &tables[2];
     }
 
     pub fn rand_back(&mut self) -> Simd<u32, LANES> {
@@ -123,10 +117,18 @@ mod tests {
         }
         const LANES: usize = 8;
         let ref_values: [u32; LANES] = core::array::from_fn(|_| rng.rand());
-        let mut rng = Rand32Simd::<LANES>::from_state_interval(state, 1);
-        rng.jump(skip);
-        let simd_values = rng.rand().to_array();
+        let mut simd = Rand32Simd::<LANES>::from_state_interval(state, 1);
+        simd.jump(skip);
+        let simd_values = simd.rand().to_array();
         assert_eq!(ref_values, simd_values);
+
+        // the second jump reuses the tables of the first
+        for _ in 0..skip - 7 {
+            rng.rand();
+        }
+        let ref_values: [u32; LANES] = core::array::from_fn(|_| rng.rand());
+        simd.jump(skip);
+        assert_eq!(ref_values, simd.rand().to_array());
     }
 
     #[test]
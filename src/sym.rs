@@ -0,0 +1,223 @@
+use crate::matrix::{BitMatrix32, BitMatrix64};
+use crate::matrix_dyn::BitMatrixDyn;
+use core::fmt;
+use core::ops::{BitAnd, BitOr, BitXor, Shl, Shr};
+
+/// Word operations that are linear over GF(2). Step functions written
+/// against this trait run on plain words as well as on symbolic ones, so
+/// the transition matrix can be derived from the same code.
+///
+/// `|` is only linear when the operands never have the same bit set, which
+/// the symbolic types check.
+pub trait Word<W>:
+    Copy
+    + Default
+    + BitXor<Output = Self>
+    + BitOr<Output = Self>
+    + BitAnd<W, Output = Self>
+    + Shl<u32, Output = Self>
+    + Shr<u32, Output = Self>
+{
+}
+
+impl<T, W> Word<W> for T where
+    T: Copy
+        + Default
+        + BitXor<Output = Self>
+        + BitOr<Output = Self>
+        + BitAnd<W, Output = Self>
+        + Shl<u32, Output = Self>
+        + Shr<u32, Output = Self>
+{
+}
+
+/// Matrix of the map from `vars` input variables to the bits of `outputs`
+/// placed back to back, row `ii` holds the output bits variable `ii` feeds
+pub fn sym_matrix(vars: usize, outputs: &[&[u128]]) -> BitMatrixDyn {
+    assert!(vars <= 128, "at most 128 variables are supported");
+    let cols = outputs.iter().map(|bits| bits.len()).sum();
+    let mut out = BitMatrixDyn::zero(vars, cols);
+    let all_bits = outputs.iter().flat_map(|bits| bits.iter());
+    for (col, &bit) in all_bits.enumerate() {
+        assert!(
+            vars == 128 || bit >> vars == 0,
+            "unknown variable in output"
+        );
+        for row in 0..vars {
+            if (bit >> row) & 1 != 0 {
+                out.set(row, col, true);
+            }
+        }
+    }
+    out
+}
+
+/// Define a step function generic over `Word` together with a `const fn`
+/// twin on plain words from the same body, the latter is used for tables
+/// computed at compile time
+macro_rules! linear_fn {
+    (
+        $(#[$attr:meta])*
+        pub fn $name:ident / $const_name:ident
+        ($($arg:ident),+) : $word:ty $body:block
+    ) => {
+        $(#[$attr])*
+        pub fn $name<T: $crate::sym::Word<$word>>($($arg: T),+) -> T $body
+
+        #[doc = concat!("`const` version of [`", stringify!($name), "`]")]
+        pub const fn $const_name($($arg: $word),+) -> $word $body
+    };
+}
+pub(crate) use linear_fn;
+
+macro_rules! impl_sym {
+    ($name:ident, $ty:ty, $bits:expr, $matrix:ident) => {
+        /// Symbolic word, each bit is a XOR of input variables given as
+        /// a mask of up to 128 variables
+        #[derive(Copy, Clone, PartialEq, Eq)]
+        pub struct $name(pub [u128; $bits]);
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_tuple(stringify!($name)).field(&self.0).finish()
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                Self([0; $bits])
+            }
+        }
+
+        impl $name {
+            /// Word made of variables `first..first + BITS`
+            pub fn var(first: u32) -> Self {
+                assert!(
+                    first as usize + $bits <= 128,
+                    "variable out of range"
+                );
+                Self(core::array::from_fn(|ii| 1 << (first as usize + ii)))
+            }
+
+            pub fn bits(&self) -> &[u128] {
+                &self.0
+            }
+
+            /// Evaluate with the variables taken from the bits of `vars`
+            pub fn eval(&self, vars: u128) -> $ty {
+                let mut out = 0;
+                for (ii, bit) in self.0.iter().enumerate() {
+                    out |= ((bit & vars).count_ones() as $ty & 1) << ii;
+                }
+                out
+            }
+
+            /// Transition matrix of a word built from `var(0)`
+            pub fn matrix(&self) -> $matrix {
+                let matrix = sym_matrix($bits, &[self.bits()]);
+                $matrix::try_from(&matrix).unwrap()
+            }
+
+            /// Run `step` on a symbolic word to get its transition matrix
+            pub fn derive(step: impl FnOnce(Self) -> Self) -> $matrix {
+                step(Self::var(0)).matrix()
+            }
+        }
+
+        impl BitXor for $name {
+            type Output = Self;
+            fn bitxor(mut self, other: Self) -> Self {
+                for (dst, src) in self.0.iter_mut().zip(other.0) {
+                    *dst ^= src;
+                }
+                self
+            }
+        }
+
+        impl BitOr for $name {
+            type Output = Self;
+            fn bitor(mut self, other: Self) -> Self {
+                for (ii, (dst, src)) in
+                    self.0.iter_mut().zip(other.0).enumerate()
+                {
+                    assert!(
+                        *dst == 0 || src == 0,
+                        "bit {} is set on both sides, | is not linear",
+                        ii
+                    );
+                    *dst |= src;
+                }
+                self
+            }
+        }
+
+        impl BitAnd<$ty> for $name {
+            type Output = Self;
+            fn bitand(mut self, mask: $ty) -> Self {
+                for (ii, dst) in self.0.iter_mut().enumerate() {
+                    if (mask >> ii) & 1 == 0 {
+                        *dst = 0;
+                    }
+                }
+                self
+            }
+        }
+
+        impl Shl<u32> for $name {
+            type Output = Self;
+            fn shl(self, bits: u32) -> Self {
+                let bits = bits as usize;
+                Self(core::array::from_fn(|ii| {
+                    ii.checked_sub(bits).map_or(0, |src| self.0[src])
+                }))
+            }
+        }
+
+        impl Shr<u32> for $name {
+            type Output = Self;
+            fn shr(self, bits: u32) -> Self {
+                let bits = bits as usize;
+                Self(core::array::from_fn(|ii| {
+                    self.0.get(ii + bits).copied().unwrap_or(0)
+                }))
+            }
+        }
+    };
+}
+
+impl_sym!(SymU32, u32, 32, BitMatrix32);
+impl_sym!(SymU64, u64, 64, BitMatrix64);
+
+#[cfg(test)]
+mod tests {
+    use super::{SymU32, SymU64, Word};
+    use crate::matrix::BitMatrix32;
+
+    fn mix<T: Word<u32>>(value: T) -> T {
+        (value ^ (value << 7)) ^ ((value & 0xff00ff00) >> 3)
+    }
+
+    #[test]
+    fn test_sym() {
+        let matrix = SymU32::derive(mix);
+        let expected = (BitMatrix32::eye() ^ BitMatrix32::shl(7))
+            ^ (BitMatrix32::eye().and(0xff00ff00) >> 3);
+        assert_eq!(matrix, expected);
+        for value in [0x1337, 0xdeadbeef, 0xcafebabe] {
+            assert_eq!(mix(value), value * matrix);
+            assert_eq!(mix(SymU32::var(0)).eval(value as u128), mix(value));
+        }
+
+        let (lo, hi) = (SymU64::var(0), SymU64::var(64));
+        let mixed = (lo >> 32) ^ (hi << 3);
+        let vars = 0x1337_u128 << 64 | 0xdead_0000_0000;
+        assert_eq!(mixed.eval(vars), 0xdead ^ 0x1337 << 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "not linear")]
+    fn test_overlapping_or() {
+        let value = SymU32::var(0);
+        let _ = value | (value >> 1);
+    }
+}
@@ -1,77 +1,68 @@
-use crate::matrix::{
-    BitMatrix128 as M128, BitMatrix32 as M32, Error as MatError,
+use crate::layout::VariableLayout;
+use crate::matrix::BitMatrix128 as M128;
+use crate::rand32::{
+    layout_to_state, rng_layout, rng_unknown_outputs, RNG_UNKNOWNS,
 };
-use crate::rand32::rng_matrix;
 
-fn build_inv_matrix() -> Result<M128, MatError> {
-    let [m1, m2, m3] = rng_matrix();
-    let pows: [[M32; 3]; 4] = core::array::from_fn(|ii| {
-        let pow = ii + 1;
-        [m1.pow(pow), m2.pow(pow), m3.pow(pow)]
-    });
-    let mut arr = [0_u128; 128];
-    for mat in 0..3 {
-        for row in 0..32 {
-            let mut row_val = 0;
-            for (ii, pow) in pows.iter().enumerate() {
-                let val = pow[mat].0[row] as u128;
-                let val = val << (ii * 32);
-                row_val |= val;
-            }
+/// Bits of the first four outputs that two u56 values reveal, as the first
+/// bit and the number of bits, in the order they are packed: the low 24
+/// bits of the first and the third output, all of the second one and bits
+/// 4..12 of the fourth one
+const OBSERVED_BITS: [(u32, u32); 4] = [(0, 24), (0, 32), (0, 24), (4, 8)];
 
-            let o_row = mat * 32 + row;
-            arr[o_row] = row_val;
-        }
+/// Pack the observed bits of the first four outputs into one vector
+const fn observed_vector(outputs: [u32; 4]) -> u128 {
+    let mut vector = 0;
+    let mut offset = 0;
+    let mut jj = 0;
+    while jj < 4 {
+        let (start, len) = OBSERVED_BITS[jj];
+        let bits = (outputs[jj] >> start) as u128 & ((1 << len) - 1);
+        vector |= bits << offset;
+        offset += len;
+        jj += 1;
+    }
+    vector
+}
+
+/// Inverse of the map from the unknown state bits to the observed output
+/// bits, computed at compile time
+static INV_MATRIX: M128 = build_inv_matrix();
+
+const fn build_inv_matrix() -> M128 {
+    let mut rows = [0; 128];
+    let mut ii = 0;
+    while ii < RNG_UNKNOWNS {
+        rows[ii] = observed_vector(rng_unknown_outputs::<4>(ii));
+        ii += 1;
     }
-    let mut mat = M128(arr);
-    // skip under-constrained input bits
-    mat = mat.vskip(64..68).vskip(32..35).vskip(0..1);
-    // skip under-specified output bits (&0x00ffffff)
-    // note 96+4
-    mat = mat
-        .hskip(88..96 + 4)
-        .hskip(24..32)
-        // skip over-specified input
-        .hskip(88..128);
     // fill the rest with ones;
-    for ii in 88..128 {
-        mat.0[ii] = 1 << ii;
+    while ii < 128 {
+        rows[ii] = 1 << ii;
+        ii += 1;
+    }
+    match M128(rows).const_inv() {
+        Ok(inv) => inv,
+        Err(_) => panic!("u56 observation matrix is not invertible"),
     }
-    mat.inv()
 }
 
 fn two_u56_to_vector(val1: u64, val2: u64) -> u128 {
-    let v0 = (val1 >> 32) as u128;
-    let v1 = ((val1 << 32) >> 32) as u128;
-    let v2 = (val2 >> 32) as u128;
-    // note 4 skipped bits
-    let v3 = ((val2 << 32) >> (32 + 4)) as u128;
-    let vector = v0 | (v1 << 24) | (v2 << 56) | (v3 << 80);
-    // mask_off extra bits
-    let mask = 128 - 88;
-    (vector << mask) >> mask
-}
-
-fn vector_to_seed(mut vector: u128) -> (u32, u32, u32) {
-    let max_u32 = 0xffff_ffff;
-    let s1 = ((vector << 1) & max_u32) as u32;
-    vector >>= 32 - 1;
-    let s2 = ((vector << 3) & max_u32) as u32;
-    vector >>= 32 - 3;
-    let s3 = ((vector << 4) & max_u32) as u32;
-    (s1, s2, s3)
+    // a u56 is the low 24 bits of one output followed by the next output
+    observed_vector([val1 >> 32, val1, val2 >> 32, val2].map(|val| val as u32))
 }
 
 pub struct U56ToSeed {
+    layout: VariableLayout,
     inv_matrix: M128,
 }
 
 impl U56ToSeed {
     pub fn new() -> Self {
-        let inv_matrix = build_inv_matrix()
-            .expect("could not build inverse matrix, blame Desu");
-
-        Self { inv_matrix }
+        Self {
+            layout: rng_layout(),
+            inv_matrix: INV_MATRIX,
+        }
     }
 
     pub fn solve(&self, val1: u64, val2: u64) -> (u32, u32, u32) {
@@ -81,7 +72,7 @@ impl U56ToSeed {
         );
         let vector = two_u56_to_vector(val1, val2);
         let out_vector = vector * self.inv_matrix;
-        vector_to_seed(out_vector)
+        layout_to_state(&self.layout, out_vector)
     }
 }
 
@@ -93,8 +84,37 @@ impl Default for U56ToSeed {
 
 #[cfg(test)]
 mod test {
-    use super::U56ToSeed;
-    use crate::rand32::Rand32Ref;
+    use super::{U56ToSeed, INV_MATRIX};
+    use crate::layout::VariableLayout;
+    use crate::matrix::{BitMatrix128 as M128, Error as MatError};
+    use crate::matrix_dyn::BitMatrixDyn;
+    use crate::rand32::{rng_layout, rng_outputs_matrix, Rand32Ref};
+
+    fn build_inv_matrix(layout: &VariableLayout) -> Result<M128, MatError> {
+        // four outputs in terms of the 96-bit state, padded to a square
+        let outputs = rng_outputs_matrix(4);
+        let padding = BitMatrixDyn::zero(32, 0);
+        let mut mat =
+            M128::try_from(&BitMatrixDyn::block_diag(&[&outputs, &padding]))?;
+        // keep only the unknown state bits
+        mat = mat.permute_rows(&layout.state_positions());
+        // observed output bits: the low 24 bits of the first and the third
+        // output, all of the second one and bits 4..12 of the fourth one
+        let cols: Vec<usize> =
+            (0..24).chain(32..88).chain(96 + 4..96 + 12).collect();
+        mat = mat.permute_cols(&cols);
+        // fill the rest with ones;
+        for ii in layout.len()..128 {
+            mat.0[ii] = 1 << ii;
+        }
+        mat.inv()
+    }
+
+    #[test]
+    fn test_inv_matrix() {
+        let runtime = build_inv_matrix(&rng_layout()).unwrap();
+        assert_eq!(INV_MATRIX, runtime);
+    }
 
     #[test]
     fn test_example() {
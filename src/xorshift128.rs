@@ -1,39 +1,98 @@
 use super::matrix::BitMatrix128;
-struct XorShift128Rng {
+use crate::poly::Poly;
+use crate::sym::{linear_fn, sym_matrix, SymU64, Word};
+use std::sync::OnceLock;
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct XorShift128Rng {
     pub state0: u64,
     pub state1: u64,
 }
 
 impl XorShift128Rng {
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> (u64, u64) {
-        let &mut Self { state0: mut s1, state1: s0 } = self;
+        let (s0, s1) = step(self.state0, self.state1);
         self.state0 = s0;
-        s1 ^= s1 << 23;
-        s1 ^= s1 >> 17;
-        s1 ^= s0;
-        s1 ^= s0 >> 26;
         self.state1 = s1;
         (s0, s1)
     }
 
+    /// Step matrix for the state `state1 << 64 | state0`
     pub fn build_matrix() -> BitMatrix128 {
-        let mut out = BitMatrix128::eye() >> 64; // state0 = state1
-        let state1 = BitMatrix128::eye() << 64;
-        out = out ^ state1;
-        out = out ^ state1 << 23;
-        out = out ^ (state1 << 23) >> 17;
-        out = out ^ (state1 >> (64 + 17)) << 64;
-        let state0 = BitMatrix128::eye().select(0..64);
-        out = out ^ state0;                       // state1 ^= stat0
-        out = out ^ (state0 >> 26).select(0..64); // state1 ^= state0 >> 26
-        out
+        let (s0, s1) = step(SymU64::var(0), SymU64::var(64));
+        let matrix = sym_matrix(128, &[s0.bits(), s1.bits()]);
+        BitMatrix128::try_from(&matrix).unwrap()
+    }
+
+    /// Jump polynomial for advancing the generator by `steps`, it only
+    /// depends on the distance and can be reused for any state
+    pub fn jump_poly(steps: u128) -> Poly {
+        Poly::x().powmod(steps, Self::charpoly())
+    }
+
+    /// Characteristic polynomial of the step matrix, computed on first use
+    pub fn charpoly() -> &'static Poly {
+        static CHARPOLY: OnceLock<Poly> = OnceLock::new();
+        CHARPOLY.get_or_init(|| Self::build_matrix().charpoly())
+    }
+
+    /// Advance the state by the distance `poly` was built for
+    pub fn jump_by(&mut self, poly: &Poly) {
+        let state = (self.state1 as u128) << 64 | self.state0 as u128;
+        let state = poly.apply(state, |state| {
+            let mut rng = Self {
+                state0: state as u64,
+                state1: (state >> 64) as u64,
+            };
+            rng.next();
+            (rng.state1 as u128) << 64 | rng.state0 as u128
+        });
+        self.state0 = state as u64;
+        self.state1 = (state >> 64) as u64;
+    }
+
+    pub fn jump(&mut self, steps: u128) {
+        self.jump_by(&Self::jump_poly(steps));
+    }
+}
+
+/// XorShift128 state step, generic over the word type
+pub fn step<T: Word<u64>>(state0: T, state1: T) -> (T, T) {
+    (state1, next_state1(state0, state1))
+}
+
+linear_fn! {
+    /// The new `state1` of a step, the new `state0` is the old `state1`
+    pub fn next_state1 / next_state1_const (state0, state1): u64 {
+        let (mut s1, s0) = (state0, state1);
+        s1 = s1 ^ (s1 << 23);
+        s1 = s1 ^ (s1 >> 17);
+        s1 = s1 ^ s0;
+        s1 ^ (s0 >> 26)
+    }
+}
+
+/// Inverse of the step matrix, computed at compile time
+static INV_MATRIX: BitMatrix128 = build_inv_matrix();
+
+const fn build_inv_matrix() -> BitMatrix128 {
+    let mut rows = [0; 128];
+    let mut ii = 0;
+    while ii < 128 {
+        let state = 1_u128 << ii;
+        let (s0, s1) = (state as u64, (state >> 64) as u64);
+        rows[ii] = (next_state1_const(s0, s1) as u128) << 64 | s1 as u128;
+        ii += 1;
+    }
+    match BitMatrix128(rows).const_inv() {
+        Ok(inv) => inv,
+        Err(_) => panic!("xorshift128 step is not invertible"),
     }
 }
 
 pub fn invert(s0: u64, s1: u64) -> (u64, u64) {
-    let matrix = XorShift128Rng::build_matrix().inv().unwrap();
     let state = (s1 as u128) << 64 | s0 as u128;
-    let state = state * matrix;
+    let state = state * INV_MATRIX;
     let s0 = ((state << 64) >> 64) as u64;
     let s1 = (state >> 64) as u64;
     (s0, s1)
@@ -53,6 +112,18 @@ mod test {
         let s0 = ((forward << 64) >> 64) as u64;
         let s1 = (forward >> 64) as u64;
         assert_eq!((f0, f1), (s0, s1));
+
+        // the same step written out as matrix operations
+        let mut out = BitMatrix128::eye() >> 64; // state0 = state1
+        let state1 = BitMatrix128::eye() << 64;
+        out = out ^ state1;
+        out = out ^ state1 << 23;
+        out = out ^ (state1 << 23) >> 17;
+        out = out ^ (state1 >> (64 + 17)) << 64;
+        let state0 = BitMatrix128::eye().select(0..64);
+        out = out ^ state0;                       // state1 ^= stat0
+        out = out ^ (state0 >> 26).select(0..64); // state1 ^= state0 >> 26
+        assert_eq!(mat, out);
     }
 
     #[test]
@@ -63,6 +134,30 @@ mod test {
         let (f0, f1) = XorShift128Rng { state0, state1 }.next();
         let (r0, r1) = invert(f0, f1);
         assert_eq!((state0, state1), (r0, r1));
+
+        let runtime = XorShift128Rng::build_matrix().inv().unwrap();
+        assert_eq!(INV_MATRIX, runtime);
+    }
+
+    #[test]
+    fn test_xs128_jump() {
+        let state0 = 0x1337133713371337;
+        let state1 = 0xdeadbeefdeadbeef;
+        let mut rng = XorShift128Rng { state0, state1 };
+        let mut jumped = XorShift128Rng { ..rng };
+        for _ in 0..10000 {
+            rng.next();
+        }
+        jumped.jump(10000);
+        assert_eq!((rng.state0, rng.state1), (jumped.state0, jumped.state1));
+
+        // the period is 2^128 - 1
+        jumped.jump(u128::MAX);
+        assert_eq!((rng.state0, rng.state1), (jumped.state0, jumped.state1));
+
+        let charpoly = XorShift128Rng::charpoly();
+        assert_eq!(charpoly.degree(), Some(128));
+        assert!(core::ptr::eq(charpoly, XorShift128Rng::charpoly()));
     }
 }
 